@@ -0,0 +1,311 @@
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+pub const DEFAULT_BUFFER_SIZE: u32 = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<(u8, u8, u8)>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![(0, 0, 0); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<(u8, u8, u8)> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    // writes outside of the screen are dropped, like a real server would
+    pub fn set(&mut self, x: usize, y: usize, color: (u8, u8, u8)) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    pub fn pixels(&self) -> &[(u8, u8, u8)] {
+        &self.pixels[..]
+    }
+
+    pub fn fill(&mut self, color: (u8, u8, u8)) {
+        for px in &mut self.pixels {
+            *px = color;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    // 8-byte commands: I, P, G, p, g, f
+    Binary,
+    // newline-terminated commands: SIZE, PX, OFFSET
+    Text,
+}
+
+struct Shared {
+    framebuffer: Mutex<Framebuffer>,
+    recv_buffer_size: AtomicU32,
+    send_buffer_size: AtomicU32,
+    shutdown: AtomicBool,
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    mode: Mode,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    // listens on a free port on localhost, for tests
+    pub fn start(mode: Mode, width: usize, height: usize) -> std::io::Result<Self> {
+        Self::bind("127.0.0.1:0", mode, width, height)
+    }
+
+    pub fn bind<A: ToSocketAddrs>(addr: A, mode: Mode, width: usize, height: usize) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            framebuffer: Mutex::new(Framebuffer::new(width, height)),
+            recv_buffer_size: AtomicU32::new(DEFAULT_BUFFER_SIZE),
+            send_buffer_size: AtomicU32::new(DEFAULT_BUFFER_SIZE),
+            shutdown: AtomicBool::new(false),
+        });
+        let acceptor_shared = shared.clone();
+        let acceptor = std::thread::spawn(move || accept_loop(listener, mode, acceptor_shared));
+        Ok(Self {
+            addr,
+            mode,
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // buffer sizes reported by the binary I command
+    pub fn set_buffer_sizes(&self, recv_buffer_size: u32, send_buffer_size: u32) {
+        self.shared.recv_buffer_size.store(recv_buffer_size, Ordering::SeqCst);
+        self.shared.send_buffer_size.store(send_buffer_size, Ordering::SeqCst);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<(u8, u8, u8)> {
+        self.shared.framebuffer.lock().unwrap().get(x, y)
+    }
+
+    pub fn set_pixel(&self, x: usize, y: usize, color: (u8, u8, u8)) {
+        self.shared.framebuffer.lock().unwrap().set(x, y, color);
+    }
+
+    pub fn snapshot(&self) -> Framebuffer {
+        self.shared.framebuffer.lock().unwrap().clone()
+    }
+
+    pub fn with_framebuffer<T>(&self, f: impl FnOnce(&mut Framebuffer) -> T) -> T {
+        f(&mut self.shared.framebuffer.lock().unwrap())
+    }
+
+    // blocks until the accept thread exits, which only happens on shutdown
+    pub fn join(mut self) {
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(acceptor) = self.acceptor.take() {
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+            // wake up the accept call so the thread sees the flag
+            let _ = TcpStream::connect(self.addr);
+            let _ = acceptor.join();
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, mode: Mode, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let shared = shared.clone();
+        std::thread::spawn(move || {
+            // connection errors only end this connection
+            let _ = match mode {
+                Mode::Binary => handle_binary(stream, &shared),
+                Mode::Text => handle_text(stream, &shared),
+            };
+        });
+    }
+}
+
+fn decode_coords(data: &[u8]) -> (usize, usize) {
+    let x = data[1] as usize | ((data[2] as usize) << 8);
+    let y = data[3] as usize | ((data[4] as usize) << 8);
+    (x, y)
+}
+
+fn decode_rect(data: &[u8]) -> (usize, usize, usize, usize) {
+    let (x, y) = decode_coords(data);
+    let w = data[5] as usize | (((data[7] & 0x0f) as usize) << 8);
+    let h = data[6] as usize | (((data[7] & 0xf0) as usize) << 4);
+    (x, y, w, h)
+}
+
+fn handle_binary(stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut command = [0u8; 8];
+    loop {
+        if reader.buffer().is_empty() {
+            // about to block on the client, so it has to see our answers first
+            writer.flush()?;
+        }
+        match reader.read_exact(&mut command[..]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        match command[0] {
+            b'I' => {
+                let (width, height) = {
+                    let fb = shared.framebuffer.lock().unwrap();
+                    (fb.width as u32, fb.height as u32)
+                };
+                writer.write_all(&width.to_le_bytes())?;
+                writer.write_all(&height.to_le_bytes())?;
+                writer.write_all(&shared.recv_buffer_size.load(Ordering::SeqCst).to_le_bytes())?;
+                writer.write_all(&shared.send_buffer_size.load(Ordering::SeqCst).to_le_bytes())?;
+            }
+            b'P' => {
+                let (x, y) = decode_coords(&command[..]);
+                shared.framebuffer.lock().unwrap().set(x, y, (command[5], command[6], command[7]));
+            }
+            b'G' => {
+                let (x, y) = decode_coords(&command[..]);
+                let color = shared.framebuffer.lock().unwrap().get(x, y).unwrap_or((0, 0, 0));
+                writer.write_all(&[color.0, color.1, color.2, 0])?;
+            }
+            b'p' => {
+                let (x, y, w, h) = decode_rect(&command[..]);
+                let mut data = vec![0u8; w * h * 4];
+                reader.read_exact(&mut data[..])?;
+                let mut fb = shared.framebuffer.lock().unwrap();
+                for dy in 0..h {
+                    for dx in 0..w {
+                        let i = (dy * w + dx) * 4;
+                        fb.set(x + dx, y + dy, (data[i], data[i + 1], data[i + 2]));
+                    }
+                }
+            }
+            b'g' => {
+                let (x, y, w, h) = decode_rect(&command[..]);
+                let mut data = Vec::with_capacity(w * h * 4);
+                {
+                    let fb = shared.framebuffer.lock().unwrap();
+                    for dy in 0..h {
+                        for dx in 0..w {
+                            let color = fb.get(x + dx, y + dy).unwrap_or((0, 0, 0));
+                            data.extend_from_slice(&[color.0, color.1, color.2, 0]);
+                        }
+                    }
+                }
+                writer.write_all(&data[..])?;
+            }
+            b'f' => {
+                let (x, y, w, h) = decode_rect(&command[..]);
+                let mut color = [0u8; 4];
+                reader.read_exact(&mut color[..])?;
+                let mut fb = shared.framebuffer.lock().unwrap();
+                for dy in 0..h {
+                    for dx in 0..w {
+                        fb.set(x + dx, y + dy, (color[0], color[1], color[2]));
+                    }
+                }
+            }
+            _ => {
+                // unknown command, the stream is out of sync from here on
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn parse_hex_color(s: &str) -> Option<(u8, u8, u8)> {
+    if (s.len() != 6 && s.len() != 8) || !s.is_ascii() {
+        return None;
+    }
+    let r = u8::from_str_radix(&s[0..2], 16).ok()?;
+    let g = u8::from_str_radix(&s[2..4], 16).ok()?;
+    let b = u8::from_str_radix(&s[4..6], 16).ok()?;
+    Some((r, g, b))
+}
+
+fn handle_text(stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut offset = (0usize, 0usize);
+    let mut line = String::new();
+    loop {
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts[..] {
+            ["SIZE"] => {
+                let fb = shared.framebuffer.lock().unwrap();
+                writeln!(writer, "SIZE {} {}", fb.width, fb.height)?;
+            }
+            ["OFFSET", x, y] => {
+                if let (Ok(x), Ok(y)) = (x.parse(), y.parse()) {
+                    offset = (x, y);
+                }
+            }
+            ["PX", x, y] => {
+                if let (Ok(x), Ok(y)) = (x.parse::<usize>(), y.parse::<usize>()) {
+                    let color = shared.framebuffer.lock().unwrap()
+                        .get(x + offset.0, y + offset.1)
+                        .unwrap_or((0, 0, 0));
+                    writeln!(writer, "PX {} {} {:02x}{:02x}{:02x}", x, y, color.0, color.1, color.2)?;
+                }
+            }
+            ["PX", x, y, color] => {
+                if let (Ok(x), Ok(y), Some(color)) = (x.parse::<usize>(), y.parse::<usize>(), parse_hex_color(color)) {
+                    shared.framebuffer.lock().unwrap().set(x + offset.0, y + offset.1, color);
+                }
+            }
+            _ => {
+                // real servers ignore garbage lines as well
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use mockserver::{MockServer, Mode};
+
+fn usage() -> ! {
+    eprintln!("usage: mockserver [--text] [--port PORT] [--size WIDTHxHEIGHT]");
+    std::process::exit(1);
+}
+
+fn main() -> std::io::Result<()> {
+    let mut mode = Mode::Binary;
+    let mut port: u16 = 1337;
+    let mut size = (1024usize, 1024usize);
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--text" => mode = Mode::Text,
+            "--port" => {
+                port = args.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| usage());
+            }
+            "--size" => {
+                let s = args.next().unwrap_or_else(|| usage());
+                size = match s.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+                    Some((Ok(w), Ok(h))) => (w, h),
+                    _ => usage(),
+                };
+            }
+            _ => usage(),
+        }
+    }
+
+    let server = MockServer::bind(("127.0.0.1", port), mode, size.0, size.1)?;
+    println!("{:?} mock server listening on {} ({}x{})", mode, server.addr(), size.0, size.1);
+    server.join();
+    Ok(())
+}
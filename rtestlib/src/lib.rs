@@ -2,7 +2,7 @@ use libc::{c_void, free};
 use libc::{malloc, size_t};
 
 #[repr(C)]
-struct Pixel {
+pub struct Pixel {
     x: u32,
     y: u32,
     r: u8,
@@ -16,6 +16,8 @@ struct MyIter {
 
 // TODO use std::alloc instead
 
+/// # Safety
+/// The returned pointer must only be passed to `iter_next` and `iter_destroy`.
 #[no_mangle]
 pub unsafe extern "C" fn iter_create(_arg: *const c_void) -> *mut c_void {
     let res: *mut MyIter = malloc(std::mem::size_of::<MyIter>() as size_t) as *mut MyIter;
@@ -26,11 +28,15 @@ pub unsafe extern "C" fn iter_create(_arg: *const c_void) -> *mut c_void {
     res as *mut c_void
 }
 
+/// # Safety
+/// `it` must come from `iter_create` and must not be used afterwards.
 #[no_mangle]
 pub unsafe extern "C" fn iter_destroy(it: *mut c_void) {
     free(it);
 }
 
+/// # Safety
+/// `it` must come from `iter_create`, `px` must point to a writable `Pixel`.
 #[no_mangle]
 pub unsafe extern "C" fn iter_next(it: *mut c_void, px: *mut Pixel) -> i32 {
     let my: *mut MyIter = it as *mut MyIter;
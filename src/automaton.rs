@@ -0,0 +1,422 @@
+use std::io::{Read, Write};
+
+use crate::canvas::Canvas;
+use crate::error::Result;
+use crate::primitive::Rect;
+use crate::protocol::Protocol;
+use crate::session::{Pacer, Session};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rule {
+    // indexed by the number of alive neighbors
+    pub birth: [bool; 9],
+    pub survive: [bool; 9],
+    // 2 for Life-like rules, Generations rules add states for dying cells
+    pub states: u8,
+}
+
+impl Rule {
+    pub fn life() -> Self {
+        "B3/S23".parse().unwrap()
+    }
+}
+
+fn neighbor_counts(digits: &str, rule: &str) -> std::result::Result<[bool; 9], String> {
+    let mut counts = [false; 9];
+    for c in digits.chars() {
+        match c.to_digit(10) {
+            Some(n) if n <= 8 => counts[n as usize] = true,
+            _ => return Err(format!("invalid neighbor count {:?} in rule {:?}", c, rule)),
+        }
+    }
+    Ok(counts)
+}
+
+// B3/S23 or S23/B3 for Life-like rules, B2/S/C3 for Generations,
+// and the older numeric forms 23/3 (S/B) and /2/3 (S/B/C)
+impl std::str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('/').map(str::trim).collect();
+        let mut birth = None;
+        let mut survive = None;
+        let mut states = 2;
+        if parts.iter().any(|p| p.starts_with(|c: char| c.is_ascii_alphabetic())) {
+            for part in &parts {
+                let mut chars = part.chars();
+                match chars.next().map(|c| c.to_ascii_uppercase()) {
+                    Some('B') => birth = Some(neighbor_counts(chars.as_str(), s)?),
+                    Some('S') => survive = Some(neighbor_counts(chars.as_str(), s)?),
+                    Some('C') | Some('G') => {
+                        states = chars.as_str().parse().map_err(|_| format!("invalid number of states in rule {:?}", s))?
+                    }
+                    _ => return Err(format!("invalid rule {:?}", s)),
+                }
+            }
+        } else {
+            match parts[..] {
+                [s_part, b_part] => {
+                    survive = Some(neighbor_counts(s_part, s)?);
+                    birth = Some(neighbor_counts(b_part, s)?);
+                }
+                [s_part, b_part, c_part] => {
+                    survive = Some(neighbor_counts(s_part, s)?);
+                    birth = Some(neighbor_counts(b_part, s)?);
+                    states = c_part.parse().map_err(|_| format!("invalid number of states in rule {:?}", s))?;
+                }
+                _ => return Err(format!("invalid rule {:?}, expected e.g. B3/S23", s)),
+            }
+        }
+        if states < 2 {
+            return Err(format!("rule {:?} needs at least 2 states", s));
+        }
+        Ok(Rule {
+            birth: birth.ok_or_else(|| format!("rule {:?} has no B part", s))?,
+            survive: survive.unwrap_or([false; 9]),
+            states,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Topology {
+    // cells outside of the grid are dead
+    Bounded,
+    // the edges wrap around
+    Torus,
+}
+
+impl std::str::FromStr for Topology {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bounded" => Ok(Topology::Bounded),
+            "torus" => Ok(Topology::Torus),
+            _ => Err(format!("unknown topology {:?}, expected bounded or torus", s)),
+        }
+    }
+}
+
+// how cells look on the canvas and how canvas colors are read back as cells
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ColorMap {
+    pub alive: (u8, u8, u8),
+    pub dead: (u8, u8, u8),
+    // colors at least this bright on average count as alive
+    pub threshold: u8,
+}
+
+impl Default for ColorMap {
+    fn default() -> Self {
+        Self { alive: (255, 255, 255), dead: (0, 0, 0), threshold: 128 }
+    }
+}
+
+impl ColorMap {
+    // dying cells of Generations rules fade from the alive to the dead color
+    pub fn color(&self, state: u8, rule: &Rule) -> (u8, u8, u8) {
+        match state {
+            0 => self.dead,
+            1 => self.alive,
+            _ => {
+                let t = (state - 1) as u32;
+                let n = (rule.states - 1) as u32;
+                let mix = |a: u8, d: u8| ((a as u32 * (n - t) + d as u32 * t) / n) as u8;
+                (mix(self.alive.0, self.dead.0), mix(self.alive.1, self.dead.1), mix(self.alive.2, self.dead.2))
+            }
+        }
+    }
+
+    // exact fade colors are recognized, everything else goes by brightness
+    pub fn state(&self, color: (u8, u8, u8), rule: &Rule) -> u8 {
+        if color == self.alive {
+            return 1;
+        }
+        if color == self.dead {
+            return 0;
+        }
+        if let Some(state) = (2..rule.states).find(|&s| self.color(s, rule) == color) {
+            return state;
+        }
+        let brightness = (color.0 as u32 + color.1 as u32 + color.2 as u32) / 3;
+        if brightness >= self.threshold as u32 {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+pub struct Automaton {
+    pub width: usize,
+    pub height: usize,
+    pub rule: Rule,
+    pub topology: Topology,
+    cells: Vec<u8>,
+    next: Vec<u8>,
+}
+
+impl Automaton {
+    pub fn new(width: usize, height: usize, rule: Rule, topology: Topology) -> Self {
+        Self {
+            width,
+            height,
+            rule,
+            topology,
+            cells: vec![0; width * height],
+            next: vec![0; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.cells[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, state: u8) {
+        self.cells[y * self.width + x] = state.min(self.rule.states - 1);
+    }
+
+    pub fn cells(&self) -> &[u8] {
+        &self.cells[..]
+    }
+
+    pub fn randomize(&mut self, density: f64) {
+        for cell in &mut self.cells {
+            *cell = if fastrand::f64() < density { 1 } else { 0 };
+        }
+    }
+
+    pub fn load_colors(&mut self, colors: &[(u8, u8, u8)], map: &ColorMap) {
+        for (cell, &color) in self.cells.iter_mut().zip(colors) {
+            *cell = map.state(color, &self.rule);
+        }
+    }
+
+    pub fn store_colors(&self, colors: &mut [(u8, u8, u8)], map: &ColorMap) {
+        for (color, &cell) in colors.iter_mut().zip(&self.cells) {
+            *color = map.color(cell, &self.rule);
+        }
+    }
+
+    fn alive_neighbors(&self, x: usize, y: usize) -> usize {
+        let (w, h) = (self.width as isize, self.height as isize);
+        let mut count = 0;
+        for dy in -1..=1isize {
+            for dx in -1..=1isize {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let (mut nx, mut ny) = (x as isize + dx, y as isize + dy);
+                match self.topology {
+                    Topology::Bounded => {
+                        if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                            continue;
+                        }
+                    }
+                    Topology::Torus => {
+                        nx = nx.rem_euclid(w);
+                        ny = ny.rem_euclid(h);
+                    }
+                }
+                // only alive cells count, dying ones of Generations rules don't
+                if self.cells[ny as usize * self.width + nx as usize] == 1 {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    pub fn step(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let state = self.cells[i];
+                self.next[i] = match state {
+                    0 if self.rule.birth[self.alive_neighbors(x, y)] => 1,
+                    0 => 0,
+                    1 if self.rule.survive[self.alive_neighbors(x, y)] => 1,
+                    // dying cells age until they are dead
+                    _ => (state + 1) % self.rule.states,
+                };
+            }
+        }
+        std::mem::swap(&mut self.cells, &mut self.next);
+    }
+}
+
+// runs an automaton inside a region of the shared canvas
+pub struct Runner {
+    pub automaton: Automaton,
+    pub colors: ColorMap,
+    // generations per second, None runs as fast as the server allows
+    pub rate: Option<f64>,
+    // read the region before every generation, so pixels drawn by others become cells
+    pub react: bool,
+    canvas: Canvas,
+    reconnects: u64,
+}
+
+impl Runner {
+    pub fn new(rect: Rect, rule: Rule, topology: Topology, colors: ColorMap) -> Self {
+        Self {
+            automaton: Automaton::new(rect.w, rect.h, rule, topology),
+            colors,
+            rate: None,
+            react: false,
+            canvas: Canvas::new(rect),
+            reconnects: 0,
+        }
+    }
+
+    pub fn with_rate(mut self, rate: Option<f64>) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    pub fn with_react(mut self, react: bool) -> Self {
+        self.react = react;
+        self
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.canvas.rect()
+    }
+
+    // start from what is on the canvas
+    pub fn fetch<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>) -> Result<()> {
+        session.run(|client| self.canvas.fetch(client))?;
+        self.automaton.load_colors(&self.canvas.screen().colors[..], &self.colors);
+        self.reconnects = session.reconnects();
+        Ok(())
+    }
+
+    // start from random cells
+    pub fn randomize<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>, density: f64, batch_size: usize) -> Result<()> {
+        self.automaton.randomize(density);
+        self.upload(session, batch_size)
+    }
+
+    fn upload<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>, batch_size: usize) -> Result<()> {
+        self.automaton.store_colors(&mut self.canvas.screen_mut().colors[..], &self.colors);
+        session.run(|client| self.canvas.upload(client, batch_size))?;
+        // a new connection may mean a restarted server, so send everything next time
+        if session.reconnects() != self.reconnects {
+            self.reconnects = session.reconnects();
+            self.canvas.invalidate();
+        }
+        Ok(())
+    }
+
+    pub fn step<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>, batch_size: usize) -> Result<()> {
+        if self.react {
+            self.fetch(session)?;
+        }
+        self.automaton.step();
+        self.upload(session, batch_size)
+    }
+
+    pub fn run<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>, generations: Option<usize>, batch_size: usize) -> Result<()> {
+        let mut pacer = Pacer::new(self.rate);
+        let mut generation = 0;
+        while generations.is_none_or(|n| generation < n) {
+            self.step(session, batch_size)?;
+            generation += 1;
+            pacer.wait();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(n: &[usize]) -> [bool; 9] {
+        let mut counts = [false; 9];
+        for &n in n {
+            counts[n] = true;
+        }
+        counts
+    }
+
+    fn alive(automaton: &Automaton) -> Vec<(usize, usize)> {
+        (0..automaton.height)
+            .flat_map(|y| (0..automaton.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| automaton.get(x, y) == 1)
+            .collect()
+    }
+
+    fn with_cells(width: usize, height: usize, topology: Topology, cells: &[(usize, usize)]) -> Automaton {
+        let mut automaton = Automaton::new(width, height, Rule::life(), topology);
+        for &(x, y) in cells {
+            automaton.set(x, y, 1);
+        }
+        automaton
+    }
+
+    #[test]
+    fn parses_rules() {
+        let life = Rule { birth: counts(&[3]), survive: counts(&[2, 3]), states: 2 };
+        assert_eq!("B3/S23".parse::<Rule>().unwrap(), life);
+        assert_eq!("S23/B3".parse::<Rule>().unwrap(), life);
+        assert_eq!("b3/s23".parse::<Rule>().unwrap(), life);
+        assert_eq!("23/3".parse::<Rule>().unwrap(), life);
+        assert_eq!(Rule::life(), life);
+
+        let brians_brain = Rule { birth: counts(&[2]), survive: counts(&[]), states: 3 };
+        assert_eq!("B2/S/C3".parse::<Rule>().unwrap(), brians_brain);
+        assert_eq!("/2/3".parse::<Rule>().unwrap(), brians_brain);
+        assert_eq!("B36/S23".parse::<Rule>().unwrap().birth, counts(&[3, 6]));
+
+        for s in ["", "B9/S23", "X3/S23", "S23", "B3/S23/C1", "B3/S23/Cx", "1/2/3/4", "2a/3"] {
+            assert!(s.parse::<Rule>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn blinker_has_period_two() {
+        let horizontal = [(1, 2), (2, 2), (3, 2)];
+        let vertical = [(2, 1), (2, 2), (2, 3)];
+        let mut automaton = with_cells(5, 5, Topology::Bounded, &horizontal);
+        automaton.step();
+        assert_eq!(alive(&automaton), vertical);
+        automaton.step();
+        assert_eq!(alive(&automaton), horizontal);
+    }
+
+    #[test]
+    fn glider_moves_diagonally_across_a_torus() {
+        // heads right and down
+        let glider = [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)];
+        let mut automaton = with_cells(6, 6, Topology::Torus, &glider);
+        for _ in 0..4 {
+            automaton.step();
+        }
+        let mut moved: Vec<_> = glider.iter().map(|&(x, y)| (x + 1, y + 1)).collect();
+        moved.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(alive(&automaton), moved);
+
+        // after wrapping around both edges it is back where it started
+        for _ in 4..24 {
+            automaton.step();
+        }
+        let mut start = glider.to_vec();
+        start.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(alive(&automaton), start);
+    }
+
+    #[test]
+    fn dying_cells_age_and_dont_count_as_neighbors() {
+        let rule: Rule = "B2/S/C3".parse().unwrap();
+        let mut automaton = Automaton::new(4, 1, rule, Topology::Bounded);
+        automaton.set(0, 0, 1);
+        automaton.set(2, 0, 1);
+        automaton.step();
+        assert_eq!(automaton.cells(), [2, 1, 2, 0]);
+        automaton.step();
+        assert_eq!(automaton.cells(), [0, 2, 0, 0]);
+    }
+}
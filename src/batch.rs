@@ -0,0 +1,148 @@
+use std::io::Write;
+
+use crate::error::Result;
+use crate::primitive::Pixel;
+use crate::protocol::Protocol;
+
+// same as BATCH_SIZE in the C clients
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+// collects encoded pixel writes and sends them in one write_all once the buffer is full
+pub struct PixelWriter<'a, S: Write, P: Protocol> {
+    stream: &'a mut S,
+    protocol: &'a P,
+    buffer: Vec<u8>,
+    buffer_size: usize,
+    pending_pixels: u64,
+    pixels_written: u64,
+    bytes_written: u64,
+}
+
+impl<'a, S: Write, P: Protocol> PixelWriter<'a, S, P> {
+    pub fn new(stream: &'a mut S, protocol: &'a P, buffer_size: usize) -> Self {
+        Self {
+            stream,
+            protocol,
+            buffer: Vec::with_capacity(buffer_size),
+            buffer_size,
+            pending_pixels: 0,
+            pixels_written: 0,
+            bytes_written: 0,
+        }
+    }
+
+    pub fn write(&mut self, px: &Pixel) -> Result<()> {
+        self.protocol.encode_print(px, &mut self.buffer)?;
+        self.pending_pixels += 1;
+        if self.buffer.len() >= self.buffer_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn write_pixels(&mut self, pixels: &[Pixel]) -> Result<()> {
+        for px in pixels {
+            self.write(px)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.stream.write_all(&self.buffer[..])?;
+            self.bytes_written += self.buffer.len() as u64;
+            self.pixels_written += self.pending_pixels;
+            self.buffer.clear();
+            self.pending_pixels = 0;
+        }
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    // only counts pixels that actually left the buffer
+    pub fn pixels_written(&self) -> u64 {
+        self.pixels_written
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+}
+
+impl<S: Write, P: Protocol> Drop for PixelWriter<'_, S, P> {
+    fn drop(&mut self) {
+        // there is nobody left to report the error to
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{BinaryProtocol, TextProtocol};
+
+    // remembers every write separately
+    #[derive(Default)]
+    struct Chunks {
+        writes: Vec<Vec<u8>>,
+        flushes: usize,
+    }
+
+    impl Write for Chunks {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    fn pixel(x: usize) -> Pixel {
+        Pixel { x, y: 7, color: (1, 2, 3) }
+    }
+
+    #[test]
+    fn flushes_once_the_buffer_is_full() {
+        let mut stream = Chunks::default();
+        let mut writer = PixelWriter::new(&mut stream, &BinaryProtocol, 20);
+        for x in 0..5 {
+            writer.write(&pixel(x)).unwrap();
+        }
+        // the third 8 byte command filled the buffer, the last two are still waiting
+        assert_eq!(writer.pixels_written(), 3);
+        assert_eq!(writer.bytes_written(), 24);
+        writer.flush().unwrap();
+        assert_eq!(writer.pixels_written(), 5);
+        assert_eq!(writer.bytes_written(), 40);
+        drop(writer);
+        assert_eq!(stream.writes.iter().map(Vec::len).collect::<Vec<_>>(), [24, 16]);
+
+        let mut expected = Vec::new();
+        for x in 0..5 {
+            BinaryProtocol.encode_print(&pixel(x), &mut expected).unwrap();
+        }
+        assert_eq!(stream.writes.concat(), expected);
+    }
+
+    #[test]
+    fn flushes_what_is_left_when_dropped() {
+        let mut stream = Chunks::default();
+        let mut writer = PixelWriter::new(&mut stream, &TextProtocol, 1024);
+        writer.write_pixels(&[pixel(1), pixel(20)]).unwrap();
+        assert_eq!(writer.pixels_written(), 0);
+        assert_eq!(writer.bytes_written(), 0);
+        drop(writer);
+        assert_eq!(stream.writes, [b"PX 1 7 010203\nPX 20 7 010203\n".to_vec()]);
+        assert_eq!(stream.flushes, 1);
+    }
+
+    #[test]
+    fn empty_writer_sends_nothing() {
+        let mut stream = Chunks::default();
+        drop(PixelWriter::new(&mut stream, &BinaryProtocol, 16));
+        assert!(stream.writes.is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+use std::io::{Read, Write};
+
+use crate::client::PixelflutClient;
+use crate::error::Result;
+use crate::primitive::{Pixel, Rect};
+use crate::protocol::Protocol;
+
+pub struct Screen {
+    pub w: usize,
+    pub h: usize,
+    pub colors: Vec<(u8, u8, u8)>,
+}
+
+impl Screen {
+    pub fn new(w: usize, h: usize) -> Self {
+        Self { w, h, colors: vec![(0, 0, 0); w * h] }
+    }
+
+    pub fn get_neighbor_mut(&mut self, x: usize, y: usize, dx: i32, dy: i32) -> Option<&mut (u8, u8, u8)> {
+        let new_x = if dx >= 0 && x + (dx as usize) < self.w {
+            Some(x + (dx as usize))
+        } else if dx < 0 && (x as i32 + dx) >= 0 {
+            Some((x as i32 + dx) as usize)
+        } else {
+            None
+        };
+        let new_y = if dy >= 0 && y + (dy as usize) < self.h {
+            Some(y + (dy as usize))
+        } else if dy < 0 && (y as i32 + dy) >= 0 {
+            Some((y as i32 + dy) as usize)
+        } else {
+            None
+        };
+        if let (Some(nx), Some(ny)) = (new_x, new_y) {
+            Some(&mut self.colors[ny * self.w + nx])
+        } else {
+            None
+        }
+    }
+}
+
+// changed pixels at most this far apart end up in the same dirty rectangle,
+// sending 2 unchanged pixels in a binary p costs as much as one more P
+const MERGE_GAP: usize = 2;
+
+// local copy of a rectangle on the server that remembers what we last sent,
+// so only the difference has to go over the wire
+pub struct Canvas {
+    rect: Rect,
+    screen: Screen,
+    // None as long as we don't know what is on the server
+    sent: Option<Vec<(u8, u8, u8)>>,
+}
+
+impl Canvas {
+    pub fn new(rect: Rect) -> Self {
+        Self { rect, screen: Screen::new(rect.w, rect.h), sent: None }
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+
+    // coordinates are relative to the canvas rect
+    pub fn get(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        self.screen.colors[y * self.rect.w + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: (u8, u8, u8)) {
+        self.screen.colors[y * self.rect.w + x] = color;
+    }
+
+    // replaces the local state with what is on the server right now
+    pub fn fetch<S: Read + Write, P: Protocol>(&mut self, client: &mut PixelflutClient<S, P>) -> Result<()> {
+        client.rectangle_get(&mut self.screen.colors[..], self.rect)?;
+        self.sent = Some(self.screen.colors.clone());
+        Ok(())
+    }
+
+    // the next upload sends everything, e.g. after the server was restarted
+    pub fn invalidate(&mut self) {
+        self.sent = None;
+    }
+
+    fn changed(&self, i: usize) -> bool {
+        self.sent.as_ref().is_none_or(|sent| sent[i] != self.screen.colors[i])
+    }
+
+    pub fn diff(&self) -> Vec<Pixel> {
+        let rect = self.rect;
+        rect.ys_abs()
+            .flat_map(|y| rect.xs_abs().map(move |x| (x, y)))
+            .filter(|&(x, y)| self.changed(rect.index_abs(x, y)))
+            .map(|(x, y)| Pixel { x, y, color: self.screen.colors[rect.index_abs(x, y)] })
+            .collect()
+    }
+
+    // changed spans of each row, stacked into taller rectangles where rows agree
+    pub fn dirty_rects(&self) -> Vec<Rect> {
+        let mut rects: Vec<Rect> = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        for y in self.rect.ys_abs() {
+            let mut spans: Vec<(usize, usize)> = Vec::new();
+            for x in self.rect.xs_abs() {
+                if !self.changed(self.rect.index_abs(x, y)) {
+                    continue;
+                }
+                match spans.last_mut() {
+                    Some((_, end)) if x - *end <= MERGE_GAP => *end = x + 1,
+                    _ => spans.push((x, x + 1)),
+                }
+            }
+            let mut next_open = Vec::with_capacity(spans.len());
+            for (start, end) in spans {
+                match open.iter().copied().find(|&i| rects[i].x == start && rects[i].w == end - start) {
+                    Some(i) => {
+                        rects[i].h += 1;
+                        next_open.push(i);
+                    }
+                    None => {
+                        rects.push(Rect { x: start, y, w: end - start, h: 1 });
+                        next_open.push(rects.len() - 1);
+                    }
+                }
+            }
+            open = next_open;
+        }
+        rects
+    }
+
+    // every dirty rectangle goes out as whichever is cheaper, a rectangle print
+    // or single pixel writes for the pixels in it that changed
+    pub fn upload<S: Read + Write, P: Protocol>(&mut self, client: &mut PixelflutClient<S, P>, batch_size: usize) -> Result<()> {
+        let mut pixels = Vec::new();
+        let mut prints = Vec::new();
+        for rect in self.dirty_rects() {
+            let changed: Vec<Pixel> = rect.ys_abs()
+                .flat_map(|y| rect.xs_abs().map(move |x| (x, y)))
+                .filter(|&(x, y)| self.changed(self.rect.index_abs(x, y)))
+                .map(|(x, y)| Pixel { x, y, color: self.screen.colors[self.rect.index_abs(x, y)] })
+                .collect();
+            let pixel_cost: usize = changed.iter().map(|px| client.protocol().print_cost(px)).sum();
+            if client.rectangle_print_cost(rect) < pixel_cost {
+                prints.push(rect);
+            } else {
+                pixels.extend(changed);
+            }
+        }
+
+        let mut writer = client.writer(batch_size);
+        writer.write_pixels(&pixels[..])?;
+        writer.flush()?;
+        drop(writer);
+        for rect in prints {
+            let colors: Vec<(u8, u8, u8)> = rect.ys_abs()
+                .flat_map(|y| rect.xs_abs().map(move |x| (x, y)))
+                .map(|(x, y)| self.screen.colors[self.rect.index_abs(x, y)])
+                .collect();
+            client.rectangle_print(&colors[..], rect)?;
+        }
+        self.sent = Some(self.screen.colors.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::BinaryProtocol;
+    use std::io::Cursor;
+
+    // replies come from input, everything the client sends ends up in output
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn binary() -> PixelflutClient<Duplex, BinaryProtocol> {
+        let info: Vec<u8> = [1024u32, 768, 1024, 1024].iter().flat_map(|n| n.to_le_bytes()).collect();
+        let mut client = PixelflutClient::from(Duplex { input: Cursor::new(info), output: Vec::new() }).unwrap();
+        client.stream_mut().output.clear();
+        client
+    }
+
+    // a canvas whose server side is known to be black
+    fn synced(rect: Rect) -> Canvas {
+        let mut canvas = Canvas::new(rect);
+        canvas.sent = Some(canvas.screen.colors.clone());
+        canvas
+    }
+
+    fn dirty_rects(canvas: &Canvas) -> Vec<(usize, usize, usize, usize)> {
+        canvas.dirty_rects().iter().map(|r| (r.x, r.y, r.w, r.h)).collect()
+    }
+
+    #[test]
+    fn nearby_changes_share_a_rect() {
+        let mut canvas = synced(Rect { x: 10, y: 20, w: 16, h: 4 });
+        // gaps of one and two unchanged pixels are bridged, three are not
+        for x in [2, 4, 7, 11] {
+            canvas.set(x, 1, (255, 0, 0));
+        }
+        assert_eq!(dirty_rects(&canvas), [(12, 21, 6, 1), (21, 21, 1, 1)]);
+    }
+
+    #[test]
+    fn matching_spans_stack_into_taller_rects() {
+        let mut canvas = synced(Rect { x: 0, y: 0, w: 16, h: 4 });
+        for y in 0..3 {
+            canvas.set(3, y, (255, 0, 0));
+            canvas.set(5, y, (255, 0, 0));
+        }
+        // a different span in the last row starts a new rect
+        canvas.set(3, 3, (255, 0, 0));
+        canvas.set(12, 2, (255, 0, 0));
+        assert_eq!(dirty_rects(&canvas), [(3, 0, 3, 3), (12, 2, 1, 1), (3, 3, 1, 1)]);
+        assert!(synced(Rect { x: 0, y: 0, w: 16, h: 4 }).dirty_rects().is_empty());
+    }
+
+    #[test]
+    fn full_redraw_is_one_rect() {
+        let rect = Rect { x: 7, y: 3, w: 30, h: 20 };
+        let mut canvas = synced(rect);
+        canvas.invalidate();
+        assert_eq!(dirty_rects(&canvas), [(7, 3, 30, 20)]);
+
+        // and so is a frame that repaints everything
+        let mut canvas = synced(rect);
+        canvas.screen_mut().colors.fill((9, 9, 9));
+        assert_eq!(dirty_rects(&canvas), [(7, 3, 30, 20)]);
+    }
+
+    #[test]
+    fn upload_picks_the_cheaper_commands() {
+        let rect = Rect { x: 0, y: 0, w: 16, h: 8 };
+        let mut client = binary();
+        let mut canvas = synced(rect);
+        // two pixels cost 16 bytes, the 3x1 rect around them 8 + 12
+        canvas.set(0, 0, (1, 1, 1));
+        canvas.set(2, 0, (2, 2, 2));
+        // 16 pixels cost 128 bytes, the 4x4 rect 8 + 64
+        for y in 4..8 {
+            for x in 8..12 {
+                canvas.set(x, y, (3, 3, 3));
+            }
+        }
+        canvas.upload(&mut client, 1024).unwrap();
+        let output = &client.stream_mut().output;
+        assert_eq!(output.len(), 2 * 8 + 8 + 16 * 4);
+        assert_eq!((output[0], output[8], output[16]), (b'P', b'P', b'p'));
+        assert!(canvas.dirty_rects().is_empty());
+
+        // nothing changed, nothing sent
+        client.stream_mut().output.clear();
+        canvas.upload(&mut client, 1024).unwrap();
+        assert!(client.stream_mut().output.is_empty());
+    }
+}
@@ -0,0 +1,473 @@
+use std::path::PathBuf;
+
+use pixelflut::automaton::{ColorMap, Rule, Topology};
+use pixelflut::config::Overrides;
+use pixelflut::image::Filter;
+use pixelflut::convolve::{EdgeMode, Preset};
+use pixelflut::filter::Pipeline;
+use pixelflut::flame::{Flame, Variation};
+use pixelflut::ifs::Ifs;
+use pixelflut::mandel::{self, Fractal, Progression};
+use pixelflut::zoom::DoubleDouble;
+use pixelflut::dither::{Kernel, OrderedDither, Palette, Quantizer, ThresholdMap};
+use pixelflut::pool::Sharding;
+use pixelflut::primitive::{parse_color, Rect};
+
+pub const USAGE: &str = "usage: pixelflut [OPTIONS] <COMMAND> [COMMAND OPTIONS]
+
+commands:
+  tree       grow a tree from the bottom of the screen (--symmetric)
+  mandel     draw the mandelbrot set or another --formula into --rect
+             (--view, --iterations, --gradient, --coloring, --progressive)
+  ifs        draw the --ifs fractal with --count points, fitted into --rect
+  barnsley   same as ifs, which draws the barnsley fern by default
+  flame      render the --flame fractal flame from --count points into --rect
+  firework   launch --count fireworks in --rect, restoring what was beneath
+             them (--rate, --launch-rate)
+  magnet     draw magnetic field lines around --count obstacles
+  life       run a cellular automaton in --rect (--rule, --randomize, --rate,
+             --react, --count generations)
+  dither     error diffusion dithering of --rect to --palette (default black and white)
+  sharpen    apply a 3x3 sharpening kernel to --rect
+  convolve   apply the --preset kernel to --rect
+  filter     run --rect through the --filters pipeline
+  zoom       zoom into the mandelbrot set at --center in --rect, as deep as
+             double-double precision goes and then again (--zoom-factor,
+             --rate, --count frames), only with --formula mandelbrot
+  blur       average --count random squares of --size pixels
+  fill       fill --rect with --color
+  circles    draw concentric paper circles
+  image      draw the png, bmp or ppm --file into --rect (default: its own size,
+             shrunk to fit the screen)
+
+options:
+  --config PATH        profile file (default $PIXELFLUT_CONFIG, ./pixelflut.conf
+                       or ~/.config/pixelflut/profiles.conf)
+  --profile NAME       connection profile (default $PIXELFLUT_PROFILE)
+  --host HOST          server address (default 127.0.0.1)
+  --port PORT          server port (default 1337)
+  --protocol PROTOCOL  binary or text (default binary)
+  --batch-size BYTES   pixel write buffer size (default 1024)
+  --connections N      number of connections
+  --sharding MODE      round-robin or region, how tree, mandel and ifs split
+                       their pixels over the connections (default round-robin)
+  --retries N          give up after N failed connection attempts in a row
+                       (default: keep reconnecting)
+  --seed SEED          seed for the random number generator
+  --rect X,Y,W,H       target rectangle (default whole screen)
+  --color RRGGBB       color for fill and the inside of the fractal
+  --file PATH          image file
+  --filter FILTER      nearest or bilinear image scaling (default bilinear)
+  --palette COLORS     comma separated RRGGBB colors for dither
+  --palette-size N     dither to N colors picked from the rectangle itself
+  --quantizer NAME     median-cut or k-means, picks the --palette-size colors
+                       (default k-means)
+  --kernel NAME        floyd-steinberg, atkinson, jarvis-judice-ninke, stucki or
+                       sierra (default floyd-steinberg)
+  --serpentine         scan every other row right to left while dithering
+  --threshold-map MAP  bayer2, bayer4, bayer8, bayer16 or blue-noise: ordered
+                       dithering instead of error diffusion, also dithers the
+                       pixels of tree, mandel and ifs to --palette
+  --preset NAME        box:RADIUS, gaussian:SIGMA, sharpen, emboss, sobel, sobel-x
+                       or sobel-y (default gaussian:1.5)
+  --edge MODE          clamp, wrap, mirror or skip, how kernels treat pixels
+                       outside of --rect (default skip)
+  --fixed-point        convolve with 16.16 fixed point instead of floats
+  --filters LIST       comma separated filters, each NAME[:PARAMETER...]:
+                       median:RADIUS, erode:RADIUS, dilate:RADIUS, sobel,
+                       canny:LOW:HIGH:SIGMA, posterize:LEVELS, pixelate:SIZE,
+                       mosaic:SIZE:COUNT or any --preset, RADIUS up to 32
+  --formula FORMULA    mandelbrot, julia[:RE:IM], burning-ship, tricorn or
+                       multibrot[:POWER], POWER 2 to 64 (default mandelbrot)
+  --view R0,R1,I0,I1   part of the complex plane that is drawn (default: all of
+                       the fractal)
+  --iterations N       fractal iterations (default 256)
+  --escape-radius R    fractal escape radius (default 256)
+  --gradient GRADIENT  gray, fire, ocean, ultra, rainbow, evenly spaced
+                       RRGGBB,RRGGBB,... or RRGGBB@POS,... with POS from 0 to 1
+                       (default gray, flames bring their own)
+  --coloring MODE      plain, smooth or histogram fractal coloring
+                       (default smooth)
+  --progressive MODE   coarse or adam7: stream the fractal while it is computed,
+                       coarse blocks or sparse pixels first and then refined
+  --threads N          threads for --progressive and zoom (default: one per cpu)
+  --center RE,IM       zoom target, digits beyond f64 precision are kept
+                       (default -0.743643887037158704752191506114774,
+                       0.131825904205311970493132056385139)
+  --zoom-factor F      the view shrinks to F times its height every frame
+                       (default 0.95), deep zooms need more --iterations
+  --ifs IFS            fern, sierpinski, dragon, maple or semicolon separated
+                       affine transforms A,B,C,D,E,F,WEIGHT[,RRGGBB] that map
+                       (x, y) to (Ax + By + E, Cx + Dy + F) (default fern)
+  --flame FLAME        sierpinski, swirl, spherical or random[:SEED], random
+                       follows --seed (default sierpinski)
+  --variation NAME     use only this variation in every flame transform: linear,
+                       sinusoidal, spherical, swirl, horseshoe, polar,
+                       handkerchief, heart, disc or spiral
+  --gamma G            flame gamma (default 2.2)
+  --brightness B       flame brightness (default 1)
+  --count N            number of points, generations, iterations, ...
+  --size N             square size for blur
+  --symmetric          symmetric tree
+  --randomize          start life from random noise
+  --rule RULE          life-like B3/S23 or generations B2/S/C3 rule (default B3/S23)
+  --topology NAME      bounded or torus, whether life wraps around the edges
+                       (default bounded)
+  --alive-color RRGGBB color of alive cells (default ffffff)
+  --dead-color RRGGBB  color of dead cells (default 000000)
+  --threshold N        average brightness from which other colors count as
+                       alive (default 128)
+  --rate N             life generations, zoom or firework frames per second
+                       (default as fast as possible, 30 for fireworks)
+  --launch-rate N      average firework launches per second (default 2)
+  --react              read --rect before every generation, so that pixels
+                       drawn by others become cells
+
+connection options override $PIXELFLUT_HOST, $PIXELFLUT_PORT, $PIXELFLUT_PROTOCOL,
+$PIXELFLUT_BATCH_SIZE and $PIXELFLUT_CONNECTIONS, which override the profile";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    Tree,
+    Mandel,
+    Ifs,
+    Flame,
+    Firework,
+    Magnet,
+    Life,
+    Dither,
+    Sharpen,
+    Convolve,
+    Filter,
+    Zoom,
+    Blur,
+    Fill,
+    Circles,
+    Image,
+}
+
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub overrides: Overrides,
+    pub sharding: Sharding,
+    pub retries: Option<usize>,
+    pub seed: Option<u64>,
+    pub command: Command,
+    pub rect: Option<Rect>,
+    pub color: Option<(u8, u8, u8)>,
+    pub file: Option<PathBuf>,
+    pub filter: Filter,
+    pub count: Option<usize>,
+    pub size: Option<usize>,
+    pub palette: Option<Palette>,
+    pub palette_size: Option<usize>,
+    pub quantizer: Quantizer,
+    pub kernel: Kernel,
+    pub serpentine: bool,
+    pub threshold_map: Option<ThresholdMap>,
+    pub preset: Preset,
+    pub edge: EdgeMode,
+    pub fixed_point: bool,
+    pub filters: Option<Pipeline>,
+    pub symmetric: bool,
+    pub randomize: bool,
+    pub rule: Rule,
+    pub topology: Topology,
+    pub cells: ColorMap,
+    pub rate: Option<f64>,
+    pub launch_rate: f64,
+    pub react: bool,
+    pub fractal: Fractal,
+    pub progression: Option<Progression>,
+    pub threads: Option<usize>,
+    pub center: (DoubleDouble, DoubleDouble),
+    pub zoom_factor: f64,
+    pub ifs: Ifs,
+    pub flame: Flame,
+}
+
+impl Args {
+    // for pixel streams, which have no region to pick a palette from
+    pub fn ordered_dither(&self) -> Option<OrderedDither> {
+        let palette = self.palette.clone().unwrap_or_else(Palette::black_and_white);
+        self.threshold_map.clone().map(|map| OrderedDither::new(map, palette))
+    }
+}
+
+fn parse_command(s: &str) -> Option<Command> {
+    match s {
+        "tree" => Some(Command::Tree),
+        "mandel" => Some(Command::Mandel),
+        "ifs" | "barnsley" => Some(Command::Ifs),
+        "flame" => Some(Command::Flame),
+        "firework" => Some(Command::Firework),
+        "magnet" => Some(Command::Magnet),
+        "life" => Some(Command::Life),
+        "dither" => Some(Command::Dither),
+        "sharpen" => Some(Command::Sharpen),
+        "convolve" => Some(Command::Convolve),
+        "filter" => Some(Command::Filter),
+        "zoom" => Some(Command::Zoom),
+        "blur" => Some(Command::Blur),
+        "fill" => Some(Command::Fill),
+        "circles" => Some(Command::Circles),
+        "image" => Some(Command::Image),
+        _ => None,
+    }
+}
+
+pub fn parse_rect(s: &str) -> Result<Rect, String> {
+    let parts: Vec<usize> = s
+        .split(',')
+        .map(|p| p.trim().parse::<usize>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid rect {:?}, expected X,Y,W,H", s))?;
+    if let [x, y, w, h] = parts[..] {
+        Ok(Rect { x, y, w, h })
+    } else {
+        Err(format!("invalid rect {:?}, expected X,Y,W,H", s))
+    }
+}
+
+pub fn parse_center(s: &str) -> Result<(DoubleDouble, DoubleDouble), String> {
+    match s.split_once(',') {
+        Some((r, i)) => Ok((r.parse()?, i.parse()?)),
+        None => Err(format!("invalid center {:?}, expected RE,IM", s)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value {:?} for {}", value, flag))
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<f64, String> {
+    match parse_number::<f64>(flag, value)? {
+        x if x > 0.0 && x.is_finite() => Ok(x),
+        _ => Err(format!("invalid value {:?} for {}", value, flag)),
+    }
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut config = None;
+    let mut profile = None;
+    let mut overrides = Overrides::default();
+    let mut sharding = Sharding::RoundRobin;
+    let mut retries = None;
+    let mut seed = None;
+    let mut command = None;
+    let mut rect = None;
+    let mut color = None;
+    let mut file = None;
+    let mut filter = Filter::Bilinear;
+    let mut count = None;
+    let mut size = None;
+    let mut palette = None;
+    let mut palette_size = None;
+    let mut quantizer = Quantizer::KMeans;
+    let mut kernel = Kernel::FloydSteinberg;
+    let mut serpentine = false;
+    let mut threshold_map = None;
+    let mut preset = Preset::Gaussian(1.5);
+    let mut edge = EdgeMode::Skip;
+    let mut fixed_point = false;
+    let mut filters = None;
+    let mut symmetric = false;
+    let mut randomize = false;
+    let mut rule = Rule::life();
+    let mut topology = Topology::Bounded;
+    let mut cells = ColorMap::default();
+    let mut rate = None;
+    let mut launch_rate = 2.0;
+    let mut react = false;
+    let mut fractal = Fractal::default();
+    let mut formula_name = None;
+    let mut view = None;
+    let mut progression = None;
+    let mut threads = None;
+    let mut center = parse_center("-0.743643887037158704752191506114774,0.131825904205311970493132056385139")?;
+    let mut zoom_factor = 0.95;
+    let mut ifs = Ifs::fern();
+    let mut flame = None;
+    let mut variation: Option<Variation> = None;
+    let mut gamma = None;
+    let mut brightness = None;
+    let mut gradient = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if command.is_some() {
+                return Err(format!("unexpected argument {:?}", arg));
+            }
+            command = Some(parse_command(&arg).ok_or_else(|| format!("unknown command {:?}", arg))?);
+            continue;
+        }
+        // flags without a value
+        match arg.as_str() {
+            "--symmetric" => {
+                symmetric = true;
+                continue;
+            }
+            "--randomize" => {
+                randomize = true;
+                continue;
+            }
+            "--serpentine" => {
+                serpentine = true;
+                continue;
+            }
+            "--fixed-point" => {
+                fixed_point = true;
+                continue;
+            }
+            "--react" => {
+                react = true;
+                continue;
+            }
+            _ => {}
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--config" => config = Some(PathBuf::from(value)),
+            "--profile" => profile = Some(value),
+            "--host" => overrides.host = Some(value),
+            "--port" => overrides.port = Some(parse_number(&arg, &value)?),
+            "--protocol" => overrides.protocol = Some(value.parse()?),
+            "--batch-size" => overrides.batch_size = Some(parse_number(&arg, &value)?),
+            "--connections" => overrides.connections = Some(parse_number(&arg, &value)?),
+            "--sharding" => sharding = value.parse()?,
+            "--retries" => retries = Some(parse_number(&arg, &value)?),
+            "--seed" => seed = Some(parse_number(&arg, &value)?),
+            "--rect" => rect = Some(parse_rect(&value)?),
+            "--color" => color = Some(parse_color(&value)?),
+            "--file" => file = Some(PathBuf::from(value)),
+            "--filter" => filter = value.parse()?,
+            "--palette" => palette = Some(value.parse()?),
+            "--palette-size" => palette_size = Some(parse_number(&arg, &value)?),
+            "--quantizer" => quantizer = value.parse()?,
+            "--kernel" => kernel = value.parse()?,
+            "--threshold-map" => threshold_map = Some(value.parse()?),
+            "--preset" => preset = value.parse()?,
+            "--edge" => edge = value.parse()?,
+            "--filters" => filters = Some(value.parse()?),
+            "--formula" => {
+                let formula = mandel::parse_formula(&value)?;
+                formula_name = Some(value.clone());
+                fractal.view = formula.view();
+                fractal.formula = formula;
+            }
+            "--view" => view = Some(value.parse()?),
+            "--iterations" => fractal.iterations = parse_number(&arg, &value)?,
+            "--escape-radius" => {
+                let radius: f64 = parse_number(&arg, &value)?;
+                // smooth coloring takes the logarithm of the radius
+                if !(radius > 1.0 && radius.is_finite()) {
+                    return Err(format!("invalid value {:?} for {}", value, arg));
+                }
+                fractal.escape_radius = radius;
+            }
+            "--gradient" => gradient = Some(value.parse()?),
+            "--coloring" => fractal.coloring = value.parse()?,
+            "--progressive" => progression = Some(value.parse()?),
+            "--threads" => threads = Some(parse_number(&arg, &value)?),
+            "--ifs" => ifs = value.parse()?,
+            "--flame" => {
+                // parsed after --seed is known
+                value.parse::<Flame>()?;
+                flame = Some(value);
+            }
+            "--variation" => variation = Some(value.parse()?),
+            "--gamma" => gamma = Some(parse_positive(&arg, &value)?),
+            "--brightness" => brightness = Some(parse_positive(&arg, &value)?),
+            "--center" => center = parse_center(&value)?,
+            "--zoom-factor" => {
+                zoom_factor = parse_number(&arg, &value)?;
+                if !(zoom_factor > 0.0 && zoom_factor < 1.0) {
+                    return Err(format!("invalid value {:?} for {}", value, arg));
+                }
+            }
+            "--count" => count = Some(parse_number(&arg, &value)?),
+            "--size" => size = Some(parse_number(&arg, &value)?),
+            "--rule" => rule = value.parse()?,
+            "--topology" => topology = value.parse()?,
+            "--alive-color" => cells.alive = parse_color(&value)?,
+            "--dead-color" => cells.dead = parse_color(&value)?,
+            "--threshold" => cells.threshold = parse_number(&arg, &value)?,
+            "--rate" => rate = Some(parse_positive(&arg, &value)?),
+            "--launch-rate" => launch_rate = parse_positive(&arg, &value)?,
+            _ => return Err(format!("unknown option {:?}", arg)),
+        }
+    }
+
+    let command = command.ok_or("no command given")?;
+    // the view of --formula unless overridden, no matter the order
+    if let Some(view) = view {
+        fractal.view = view;
+    }
+    let mut flame = match flame.as_deref() {
+        Some("random") => Flame::random(seed.unwrap_or_else(|| fastrand::u64(..))),
+        Some(spec) => spec.parse()?,
+        None => Flame::sierpinski(),
+    };
+    if let Some(variation) = variation {
+        flame = flame.with_variation(variation);
+    }
+    flame.gamma = gamma.unwrap_or(flame.gamma);
+    flame.brightness = brightness.unwrap_or(flame.brightness);
+    if let Some(gradient) = gradient {
+        fractal.gradient = gradient;
+        flame.gradient = fractal.gradient.clone();
+    }
+    if command == Command::Image && file.is_none() {
+        return Err(String::from("image needs --file"));
+    }
+    if command == Command::Filter && filters.is_none() {
+        return Err(String::from("filter needs --filters"));
+    }
+    // perturbation only works for z^2 + c
+    if command == Command::Zoom && formula_name.as_ref().is_some_and(|f| f != "mandelbrot") {
+        return Err(String::from("zoom only works with --formula mandelbrot"));
+    }
+
+    Ok(Args {
+        config,
+        profile,
+        overrides,
+        sharding,
+        retries,
+        seed,
+        command,
+        rect,
+        color,
+        file,
+        filter,
+        count,
+        size,
+        palette,
+        palette_size,
+        quantizer,
+        kernel,
+        serpentine,
+        threshold_map,
+        preset,
+        edge,
+        fixed_point,
+        filters,
+        symmetric,
+        randomize,
+        rule,
+        topology,
+        cells,
+        rate,
+        launch_rate,
+        react,
+        fractal,
+        progression,
+        threads,
+        center,
+        zoom_factor,
+        ifs,
+        flame,
+    })
+}
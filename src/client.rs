@@ -0,0 +1,256 @@
+use std::io::{Read, Write};
+
+use crate::batch::PixelWriter;
+use crate::error::Result;
+use crate::primitive::{Pixel, Rect};
+use crate::protocol::{check_color_count, BinaryProtocol, Protocol, ServerInfo, RECT_HEADER_SIZE};
+
+pub struct PixelflutClient<S: Read + Write, P: Protocol = BinaryProtocol> {
+    stream: S,
+    protocol: P,
+    info: ServerInfo,
+}
+
+impl<S: Read + Write> PixelflutClient<S, BinaryProtocol> {
+    pub fn from(stream: S) -> Result<Self> {
+        Self::with_protocol(stream, BinaryProtocol)
+    }
+}
+
+impl<S: Read + Write, P: Protocol> PixelflutClient<S, P> {
+    // queries the server info once and caches it for the lifetime of the client
+    pub fn with_protocol(mut stream: S, protocol: P) -> Result<Self> {
+        let info = protocol.info(&mut stream)?;
+        Ok(Self { stream, protocol, info })
+    }
+
+    pub fn info(&self) -> ServerInfo {
+        self.info
+    }
+
+    pub fn refresh_info(&mut self) -> Result<ServerInfo> {
+        self.info = self.protocol.info(&mut self.stream)?;
+        Ok(self.info)
+    }
+
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    pub fn stream_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    pub fn print(&mut self, px: &Pixel) -> Result<()> {
+        self.protocol.print(px, &mut self.stream)
+    }
+
+    pub fn writer(&mut self, buffer_size: usize) -> PixelWriter<'_, S, P> {
+        PixelWriter::new(&mut self.stream, &self.protocol, buffer_size)
+    }
+
+    pub fn get(&mut self, px: &mut Pixel) -> Result<()> {
+        self.protocol.get(px, &mut self.stream)
+    }
+
+    // bytes on the wire for rectangle_print, split the same way
+    pub fn rectangle_print_cost(&self, rect: Rect) -> usize {
+        self.protocol.rectangle_print_cost(rect, self.info.recv_buffer_size)
+    }
+
+    pub fn rectangle_get(&mut self, colors: &mut [(u8, u8, u8)], rect: Rect) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        let (tile_w, tile_h) = self.protocol.tile_size(rect, self.info.send_buffer_size, 0);
+        if tile_w == rect.w && tile_h == rect.h {
+            return self.protocol.rectangle_get(colors, rect, &mut self.stream);
+        }
+        let mut tile_colors = Vec::with_capacity(tile_w * tile_h);
+        for tile in rect.tiles(tile_w, tile_h) {
+            tile_colors.resize(tile.w * tile.h, (0, 0, 0));
+            self.protocol.rectangle_get(&mut tile_colors[..], tile, &mut self.stream)?;
+            for y in tile.ys_abs() {
+                let start = rect.index_abs(tile.x, y);
+                let tile_start = tile.index_abs(tile.x, y);
+                colors[start..start + tile.w].copy_from_slice(&tile_colors[tile_start..tile_start + tile.w]);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rectangle_print(&mut self, colors: &[(u8, u8, u8)], rect: Rect) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        let (tile_w, tile_h) = self.protocol.tile_size(rect, self.info.recv_buffer_size, RECT_HEADER_SIZE);
+        if tile_w == rect.w && tile_h == rect.h {
+            return self.protocol.rectangle_print(colors, rect, &mut self.stream);
+        }
+        let mut tile_colors = Vec::with_capacity(tile_w * tile_h);
+        for tile in rect.tiles(tile_w, tile_h) {
+            tile_colors.clear();
+            for y in tile.ys_abs() {
+                let start = rect.index_abs(tile.x, y);
+                tile_colors.extend_from_slice(&colors[start..start + tile.w]);
+            }
+            self.protocol.rectangle_print(&tile_colors[..], tile, &mut self.stream)?;
+        }
+        Ok(())
+    }
+
+    pub fn rectangle_fill(&mut self, color: (u8, u8, u8), rect: Rect) -> Result<()> {
+        // fill only sends the color once, so only the wire format limits the size
+        let (tile_w, tile_h) = self.protocol.tile_size(rect, 0, 0);
+        for tile in rect.tiles(tile_w, tile_h) {
+            self.protocol.rectangle_fill(color, tile, &mut self.stream)?;
+        }
+        Ok(())
+    }
+
+    pub fn offset(&mut self, x: usize, y: usize) -> Result<()> {
+        self.protocol.offset(x, y, &mut self.stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::PixelflutError;
+    use crate::protocol::TextProtocol;
+    use std::io::Cursor;
+
+    // replies come from input, everything the client sends ends up in output
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn info_reply(width: u32, height: u32, recv: u32, send: u32) -> Vec<u8> {
+        [width, height, recv, send].iter().flat_map(|n| n.to_le_bytes()).collect()
+    }
+
+    // a binary client that already consumed its I command
+    fn binary(recv_buffer_size: u32) -> PixelflutClient<Duplex, BinaryProtocol> {
+        let input = Cursor::new(info_reply(1024, 768, recv_buffer_size, 1024));
+        let mut client = PixelflutClient::from(Duplex { input, output: Vec::new() }).unwrap();
+        assert_eq!(client.stream_mut().output, b"I\0\0\0\0\0\0\0");
+        client.stream_mut().output.clear();
+        client
+    }
+
+    #[test]
+    fn parses_the_info_reply() {
+        let input = Cursor::new(info_reply(800, 600, 4096, 2048));
+        let client = PixelflutClient::from(Duplex { input, output: Vec::new() }).unwrap();
+        let info = client.info();
+        assert_eq!((info.width, info.height, info.recv_buffer_size, info.send_buffer_size), (800, 600, 4096, 2048));
+    }
+
+    #[test]
+    fn rejects_empty_screens() {
+        let input = Cursor::new(info_reply(0, 600, 4096, 2048));
+        let result = PixelflutClient::from(Duplex { input, output: Vec::new() });
+        assert!(matches!(result, Err(PixelflutError::MalformedInfo(_))));
+    }
+
+    #[test]
+    fn reports_short_info_replies() {
+        let input = Cursor::new(vec![1, 2, 3, 4, 5]);
+        let result = PixelflutClient::from(Duplex { input, output: Vec::new() });
+        assert!(matches!(result, Err(PixelflutError::ShortRead { expected: 16, received: 5 })));
+    }
+
+    #[test]
+    fn pixel_bytes() {
+        let mut client = binary(1024);
+        client.print(&Pixel { x: 0x1234, y: 0x56, color: (1, 2, 3) }).unwrap();
+        assert_eq!(client.stream_mut().output, [b'P', 0x34, 0x12, 0x56, 0x00, 1, 2, 3]);
+    }
+
+    #[test]
+    fn pixel_out_of_range() {
+        let mut client = binary(1024);
+        let result = client.print(&Pixel { x: 0x10000, y: 0, color: (1, 2, 3) });
+        assert!(matches!(result, Err(PixelflutError::OutOfRange { x: 0x10000, y: 0 })));
+        assert!(client.stream_mut().output.is_empty());
+    }
+
+    #[test]
+    fn rectangle_fill_bytes() {
+        let mut client = binary(1024);
+        client.rectangle_fill((9, 8, 7), Rect { x: 0x102, y: 3, w: 0x345, h: 0x678 }).unwrap();
+        // low bytes of w and h, then the high nibbles of w and h in one byte
+        assert_eq!(client.stream_mut().output, [b'f', 0x02, 0x01, 0x03, 0x00, 0x45, 0x78, 0x63, 9, 8, 7, 0]);
+    }
+
+    #[test]
+    fn rectangle_print_bytes() {
+        let mut client = binary(1024);
+        let colors = [(1, 2, 3), (4, 5, 6), (7, 8, 9), (10, 11, 12)];
+        client.rectangle_print(&colors[..], Rect { x: 5, y: 6, w: 2, h: 2 }).unwrap();
+        assert_eq!(
+            client.stream_mut().output,
+            [b'p', 5, 0, 6, 0, 2, 2, 0, 1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9, 0, 10, 11, 12, 0]
+        );
+    }
+
+    #[test]
+    fn rectangle_print_splits_to_the_receive_buffer() {
+        // 8 byte header and two pixels per command
+        let mut client = binary(16);
+        let colors = [(1, 1, 1), (2, 2, 2), (3, 3, 3)];
+        client.rectangle_print(&colors[..], Rect { x: 0, y: 0, w: 3, h: 1 }).unwrap();
+        assert_eq!(
+            client.stream_mut().output,
+            [b'p', 0, 0, 0, 0, 2, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, b'p', 2, 0, 0, 0, 1, 1, 0, 3, 3, 3, 0]
+        );
+    }
+
+    #[test]
+    fn rectangle_print_cost_matches_the_bytes_sent() {
+        for buffer_size in [0, 4, 16, 64, 1024] {
+            for (w, h) in [(1, 1), (7, 5), (13, 1), (1, 13), (40, 30)] {
+                let mut client = binary(buffer_size);
+                let rect = Rect { x: 1, y: 2, w, h };
+                client.rectangle_print(&vec![(1, 2, 3); w * h][..], rect).unwrap();
+                assert_eq!(client.rectangle_print_cost(rect), client.stream_mut().output.len(), "{}x{} with {}", w, h, buffer_size);
+            }
+        }
+    }
+
+    #[test]
+    fn rectangle_print_checks_the_color_count() {
+        let mut client = binary(1024);
+        let result = client.rectangle_print(&[(0, 0, 0)][..], Rect { x: 0, y: 0, w: 2, h: 1 });
+        assert!(matches!(result, Err(PixelflutError::ColorCount { expected: 2, actual: 1 })));
+    }
+
+    #[test]
+    fn text_bytes() {
+        let input = Cursor::new(b"SIZE 320 240\nPX 1 2 0a0b0c\n".to_vec());
+        let mut client = PixelflutClient::with_protocol(Duplex { input, output: Vec::new() }, TextProtocol).unwrap();
+        assert_eq!((client.info().width, client.info().height), (320, 240));
+        client.print(&Pixel { x: 1, y: 2, color: (255, 0, 16) }).unwrap();
+        let mut px = Pixel { x: 1, y: 2, color: (0, 0, 0) };
+        client.get(&mut px).unwrap();
+        assert_eq!(px.color, (10, 11, 12));
+        assert_eq!(client.stream_mut().output, b"SIZE\nPX 1 2 ff0010\nPX 1 2\n");
+    }
+}
@@ -0,0 +1,296 @@
+use std::path::{Path, PathBuf};
+
+use crate::batch::DEFAULT_BATCH_SIZE;
+use crate::protocol::ProtocolKind;
+
+// connection settings, selected by name from the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub host: String,
+    pub port: u16,
+    pub protocol: ProtocolKind,
+    // bytes per write, also what a reconnect sends again at most
+    pub batch_size: usize,
+    pub connections: usize,
+}
+
+impl Default for Profile {
+    // the local test server, same as CONNECTION_LOCAL in the C clients
+    fn default() -> Self {
+        Self {
+            host: String::from("127.0.0.1"),
+            port: 1337,
+            protocol: ProtocolKind::Binary,
+            batch_size: DEFAULT_BATCH_SIZE,
+            connections: 1,
+        }
+    }
+}
+
+impl Profile {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid value {:?} for {}", value, key);
+        match key {
+            "host" => self.host = value.to_string(),
+            "port" => self.port = value.parse().map_err(|_| invalid())?,
+            "protocol" => self.protocol = value.parse()?,
+            "batch_size" => self.batch_size = value.parse().map_err(|_| invalid())?,
+            "connections" => self.connections = value.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("unknown key {:?}", key)),
+        }
+        Ok(())
+    }
+}
+
+// ini-style file:
+//
+//   default = event
+//
+//   [local]
+//   port = 1337
+//
+//   [event]
+//   host = 193.196.38.206
+//   port = 1234
+//   protocol = text
+//   connections = 4
+//
+// keys missing from a section keep the values of Profile::default()
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    pub profiles: Vec<(String, Profile)>,
+}
+
+impl Config {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", i + 1, e);
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(error(String::from("profile without a name")));
+                }
+                if config.profile(name).is_some() {
+                    return Err(error(format!("duplicate profile {:?}", name)));
+                }
+                config.profiles.push((name.to_string(), Profile::default()));
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or_else(|| error(format!("expected key = value, got {:?}", line)))?;
+            match config.profiles.last_mut() {
+                Some((_, profile)) => profile.set(key, value).map_err(error)?,
+                None if key == "default" => config.default_profile = Some(value.to_string()),
+                None => return Err(error(format!("key {:?} outside of a profile", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&s).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+
+    // a profile asked for by name has to exist, otherwise fall back to the defaults
+    pub fn select(&self, name: Option<&str>) -> Result<Profile, String> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self.profile(name).cloned().ok_or_else(|| format!("no profile named {:?}", name)),
+            None => Ok(Profile::default()),
+        }
+    }
+
+    // command line > environment > profile from the file > defaults
+    pub fn resolve(&self, name: Option<&str>, env: &Overrides, cli: &Overrides) -> Result<Profile, String> {
+        let mut profile = self.select(name)?;
+        env.apply(&mut profile);
+        cli.apply(&mut profile);
+        Ok(profile)
+    }
+}
+
+// $PIXELFLUT_CONFIG, ./pixelflut.conf or ~/.config/pixelflut/profiles.conf, whichever exists first
+pub fn default_config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("PIXELFLUT_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let local = PathBuf::from("pixelflut.conf");
+    if local.is_file() {
+        return Some(local);
+    }
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    let path = config_home.join("pixelflut").join("profiles.conf");
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn env_var<T: std::str::FromStr>(vars: &impl Fn(&str) -> Option<String>, var: &str) -> Result<Option<T>, String> {
+    match vars(var) {
+        Some(value) => value.parse().map(Some).map_err(|_| format!("invalid value {:?} for {}", value, var)),
+        None => Ok(None),
+    }
+}
+
+// values that take precedence over the selected profile
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub protocol: Option<ProtocolKind>,
+    pub batch_size: Option<usize>,
+    pub connections: Option<usize>,
+}
+
+impl Overrides {
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|var| std::env::var(var).ok())
+    }
+
+    // the PIXELFLUT_* variables, looked up with vars
+    pub fn from_vars(vars: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        Ok(Overrides {
+            host: vars("PIXELFLUT_HOST"),
+            port: env_var(&vars, "PIXELFLUT_PORT")?,
+            protocol: env_var(&vars, "PIXELFLUT_PROTOCOL")?,
+            batch_size: env_var(&vars, "PIXELFLUT_BATCH_SIZE")?,
+            connections: env_var(&vars, "PIXELFLUT_CONNECTIONS")?,
+        })
+    }
+
+    pub fn apply(&self, profile: &mut Profile) {
+        if let Some(host) = &self.host {
+            profile.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            profile.port = port;
+        }
+        if let Some(protocol) = self.protocol {
+            profile.protocol = protocol;
+        }
+        if let Some(batch_size) = self.batch_size {
+            profile.batch_size = batch_size;
+        }
+        if let Some(connections) = self.connections {
+            profile.connections = connections;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "
+# comments and blank lines are skipped
+default = event
+
+[local]
+port = 1338
+
+[event]
+host = 193.196.38.206
+port = 1234
+protocol = text
+connections = 4
+";
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let pairs: Vec<(String, String)> = pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        move |var| pairs.iter().find(|(k, _)| k == var).map(|(_, v)| v.clone())
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let config = Config::parse(FILE).unwrap();
+        assert_eq!(config.default_profile.as_deref(), Some("event"));
+        assert_eq!(config.profile("local"), Some(&Profile { port: 1338, ..Profile::default() }));
+        let event = config.profile("event").unwrap();
+        assert_eq!((event.host.as_str(), event.port, event.protocol), ("193.196.38.206", 1234, ProtocolKind::Text));
+        assert_eq!((event.connections, event.batch_size), (4, DEFAULT_BATCH_SIZE));
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_sections() {
+        for (s, line) in [
+            ("[local]\ncolor = red", 2),
+            ("[local]\nport = x", 2),
+            ("[local]\nprotocol = udp", 2),
+            ("port = 1234", 1),
+            ("[local]\n[]\n", 2),
+            ("[local\nport = 1", 1),
+            ("[local]\nport 1234", 2),
+            ("[local]\nport = 1\n[event]\n[local]", 4),
+        ] {
+            let error = Config::parse(s).unwrap_err();
+            assert!(error.starts_with(&format!("line {}:", line)), "{:?}: {}", s, error);
+        }
+        assert!(Config::parse("[local]\n[local]").unwrap_err().contains("duplicate profile"));
+    }
+
+    #[test]
+    fn selects_profiles_by_name_or_default() {
+        let config = Config::parse(FILE).unwrap();
+        assert_eq!(config.select(None).unwrap().port, 1234);
+        assert_eq!(config.select(Some("local")).unwrap().port, 1338);
+        assert!(config.select(Some("missing")).is_err());
+        assert_eq!(Config::default().select(None).unwrap(), Profile::default());
+        // a default that doesn't exist is an error as well
+        assert!(Config::parse("default = missing").unwrap().select(None).is_err());
+    }
+
+    #[test]
+    fn environment_overrides_the_file_and_the_command_line_overrides_both() {
+        let config = Config::parse(FILE).unwrap();
+        let env = Overrides::from_vars(vars(&[("PIXELFLUT_PORT", "4000"), ("PIXELFLUT_BATCH_SIZE", "64")])).unwrap();
+        let cli = Overrides { port: Some(5000), ..Overrides::default() };
+
+        let profile = config.resolve(Some("event"), &env, &Overrides::default()).unwrap();
+        assert_eq!((profile.port, profile.batch_size, profile.connections), (4000, 64, 4));
+
+        let profile = config.resolve(Some("event"), &env, &cli).unwrap();
+        assert_eq!((profile.port, profile.batch_size, profile.host.as_str()), (5000, 64, "193.196.38.206"));
+
+        let profile = Config::default().resolve(None, &Overrides::default(), &Overrides::default()).unwrap();
+        assert_eq!(profile, Profile::default());
+    }
+
+    #[test]
+    fn reads_every_variable() {
+        let env = Overrides::from_vars(vars(&[
+            ("PIXELFLUT_HOST", "example.org"),
+            ("PIXELFLUT_PORT", "1"),
+            ("PIXELFLUT_PROTOCOL", "text"),
+            ("PIXELFLUT_BATCH_SIZE", "2"),
+            ("PIXELFLUT_CONNECTIONS", "3"),
+        ]))
+        .unwrap();
+        let mut profile = Profile::default();
+        env.apply(&mut profile);
+        assert_eq!(profile, Profile {
+            host: String::from("example.org"),
+            port: 1,
+            protocol: ProtocolKind::Text,
+            batch_size: 2,
+            connections: 3,
+        });
+        assert!(Overrides::from_vars(vars(&[("PIXELFLUT_PORT", "70000")])).is_err());
+        assert!(Overrides::from_vars(vars(&[])).unwrap().host.is_none());
+    }
+}
@@ -0,0 +1,457 @@
+use crate::canvas::Screen;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EdgeMode {
+    // repeat the outermost pixel
+    Clamp,
+    // continue on the opposite side
+    Wrap,
+    // reflect at the edge without repeating the edge pixel
+    Mirror,
+    // leave out taps that fall outside and scale up the rest
+    Skip,
+}
+
+impl std::str::FromStr for EdgeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(EdgeMode::Clamp),
+            "wrap" => Ok(EdgeMode::Wrap),
+            "mirror" => Ok(EdgeMode::Mirror),
+            "skip" => Ok(EdgeMode::Skip),
+            _ => Err(format!("unknown edge mode {:?}, expected clamp, wrap, mirror or skip", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Accumulator {
+    Float,
+    // 16.16 fixed point integers, faster and reproducible on every machine
+    Fixed,
+}
+
+// weights are stored in row-major order, the anchor is the center
+#[derive(Debug, Clone)]
+pub struct Kernel {
+    pub width: usize,
+    pub height: usize,
+    pub weights: Vec<f64>,
+    // added to every result, e.g. to move signed edges into the visible range
+    pub bias: f64,
+    // Some((column, row)) if weights is their outer product
+    separable: Option<(Vec<f64>, Vec<f64>)>,
+}
+
+impl Kernel {
+    pub fn new(width: usize, height: usize, weights: Vec<f64>) -> Self {
+        assert_eq!(weights.len(), width * height, "kernel weights don't match its size");
+        Self { width, height, weights, bias: 0.0, separable: None }
+    }
+
+    // convolving with the row and then the column is the same as with their
+    // product, but costs w + h instead of w * h multiplications per pixel
+    pub fn separable(column: Vec<f64>, row: Vec<f64>) -> Self {
+        let weights = column.iter().flat_map(|c| row.iter().map(move |r| c * r)).collect();
+        Self {
+            width: row.len(),
+            height: column.len(),
+            weights,
+            bias: 0.0,
+            separable: Some((column, row)),
+        }
+    }
+
+    pub fn with_bias(mut self, bias: f64) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    pub fn is_separable(&self) -> bool {
+        self.separable.is_some()
+    }
+
+    pub fn box_blur(radius: usize) -> Self {
+        let taps = vec![1.0 / (2 * radius + 1) as f64; 2 * radius + 1];
+        Self::separable(taps.clone(), taps)
+    }
+
+    // cut off at 3 sigma
+    pub fn gaussian(sigma: f64) -> Self {
+        let radius = (3.0 * sigma).ceil().max(1.0) as isize;
+        let taps: Vec<f64> = (-radius..=radius).map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp()).collect();
+        let sum: f64 = taps.iter().sum();
+        let taps: Vec<f64> = taps.iter().map(|t| t / sum).collect();
+        Self::separable(taps.clone(), taps)
+    }
+
+    pub fn sharpen() -> Self {
+        Self::new(3, 3, vec![
+            0.0, -1.0, 0.0,
+            -1.0, 5.0, -1.0,
+            0.0, -1.0, 0.0,
+        ])
+    }
+
+    pub fn emboss() -> Self {
+        Self::new(3, 3, vec![
+            -2.0, -1.0, 0.0,
+            -1.0, 1.0, 1.0,
+            0.0, 1.0, 2.0,
+        ])
+    }
+
+    pub fn sobel_x() -> Self {
+        Self::separable(vec![1.0, 2.0, 1.0], vec![-1.0, 0.0, 1.0])
+    }
+
+    pub fn sobel_y() -> Self {
+        Self::separable(vec![-1.0, 0.0, 1.0], vec![1.0, 2.0, 1.0])
+    }
+}
+
+// how products of samples and weights are summed up
+trait Accumulate: Copy {
+    const ZERO: Self;
+    fn sample(v: f64) -> Self;
+    fn weight(w: f64) -> Self;
+    fn mul_add(self, sample: Self, weight: Self) -> Self;
+    fn scale(self, factor: f64) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+impl Accumulate for f64 {
+    const ZERO: Self = 0.0;
+
+    fn sample(v: f64) -> Self {
+        v
+    }
+
+    fn weight(w: f64) -> Self {
+        w
+    }
+
+    fn mul_add(self, sample: Self, weight: Self) -> Self {
+        self + sample * weight
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        self * factor
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+const FRACTION_BITS: u32 = 16;
+
+impl Accumulate for i64 {
+    const ZERO: Self = 0;
+
+    fn sample(v: f64) -> Self {
+        (v * (1 << FRACTION_BITS) as f64).round() as i64
+    }
+
+    fn weight(w: f64) -> Self {
+        Self::sample(w)
+    }
+
+    fn mul_add(self, sample: Self, weight: Self) -> Self {
+        self + ((sample * weight) >> FRACTION_BITS)
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        (self as f64 * factor).round() as i64
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64 / (1 << FRACTION_BITS) as f64
+    }
+}
+
+fn source_index(i: isize, n: usize, edge: EdgeMode) -> Option<usize> {
+    let n = n as isize;
+    if (0..n).contains(&i) {
+        return Some(i as usize);
+    }
+    match edge {
+        EdgeMode::Clamp => Some(i.clamp(0, n - 1) as usize),
+        EdgeMode::Wrap => Some(i.rem_euclid(n) as usize),
+        EdgeMode::Mirror => {
+            // -1 -> 1, n -> n - 2
+            let period = (2 * (n - 1)).max(1);
+            let m = i.rem_euclid(period);
+            Some(if m < n { m } else { period - m } as usize)
+        }
+        EdgeMode::Skip => None,
+    }
+}
+
+fn convolve_pass<A: Accumulate>(src: &[[A; 3]], w: usize, h: usize, kw: usize, kh: usize, weights: &[f64], edge: EdgeMode) -> Vec<[A; 3]> {
+    let quantized: Vec<A> = weights.iter().map(|&k| A::weight(k)).collect();
+    let total: f64 = weights.iter().sum();
+    let (ax, ay) = ((kw / 2) as isize, (kh / 2) as isize);
+    let mut dst = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let mut acc = [A::ZERO; 3];
+            let mut used = 0.0;
+            for ky in 0..kh {
+                let sy = match source_index(y as isize + ky as isize - ay, h, edge) {
+                    Some(sy) => sy,
+                    None => continue,
+                };
+                for kx in 0..kw {
+                    let sx = match source_index(x as isize + kx as isize - ax, w, edge) {
+                        Some(sx) => sx,
+                        None => continue,
+                    };
+                    let k = ky * kw + kx;
+                    let sample = src[sy * w + sx];
+                    for c in 0..3 {
+                        acc[c] = acc[c].mul_add(sample[c], quantized[k]);
+                    }
+                    used += weights[k];
+                }
+            }
+            // kernels that sum to zero (edge detectors) can't be renormalized
+            if edge == EdgeMode::Skip && used != total && used != 0.0 && total != 0.0 {
+                for a in &mut acc {
+                    *a = a.scale(total / used);
+                }
+            }
+            dst.push(acc);
+        }
+    }
+    dst
+}
+
+// signed results without bias, before they are clamped to colors
+fn convolve_raw<A: Accumulate>(screen: &Screen, kernel: &Kernel, edge: EdgeMode) -> Vec<[f64; 3]> {
+    let (w, h) = (screen.w, screen.h);
+    let src: Vec<[A; 3]> = screen.colors
+        .iter()
+        .map(|c| [A::sample(c.0 as f64), A::sample(c.1 as f64), A::sample(c.2 as f64)])
+        .collect();
+    // skip renormalizes every pass on its own, which only matches the full kernel
+    // if neither factor sums to zero, e.g. not for sobel
+    let zero_sum = |taps: &[f64]| taps.iter().sum::<f64>() == 0.0;
+    let dst = match &kernel.separable {
+        Some((column, row)) if edge != EdgeMode::Skip || !(zero_sum(column) || zero_sum(row)) => {
+            let rows = convolve_pass(&src[..], w, h, row.len(), 1, &row[..], edge);
+            convolve_pass(&rows[..], w, h, 1, column.len(), &column[..], edge)
+        }
+        _ => convolve_pass(&src[..], w, h, kernel.width, kernel.height, &kernel.weights[..], edge),
+    };
+    dst.iter().map(|a| [a[0].to_f64(), a[1].to_f64(), a[2].to_f64()]).collect()
+}
+
+fn raw(screen: &Screen, kernel: &Kernel, edge: EdgeMode, accumulator: Accumulator) -> Vec<[f64; 3]> {
+    match accumulator {
+        Accumulator::Float => convolve_raw::<f64>(screen, kernel, edge),
+        Accumulator::Fixed => convolve_raw::<i64>(screen, kernel, edge),
+    }
+}
+
+fn to_color(v: [f64; 3]) -> (u8, u8, u8) {
+    let c = |x: f64| x.round().clamp(0.0, 255.0) as u8;
+    (c(v[0]), c(v[1]), c(v[2]))
+}
+
+pub fn convolve(screen: &Screen, kernel: &Kernel, edge: EdgeMode, accumulator: Accumulator) -> Screen {
+    let colors = raw(screen, kernel, edge, accumulator)
+        .into_iter()
+        .map(|v| to_color([v[0] + kernel.bias, v[1] + kernel.bias, v[2] + kernel.bias]))
+        .collect();
+    Screen { w: screen.w, h: screen.h, colors }
+}
+
+// gradient magnitude of both sobel kernels, per channel
+pub fn sobel(screen: &Screen, edge: EdgeMode, accumulator: Accumulator) -> Screen {
+    let gx = raw(screen, &Kernel::sobel_x(), edge, accumulator);
+    let gy = raw(screen, &Kernel::sobel_y(), edge, accumulator);
+    let colors = gx
+        .iter()
+        .zip(&gy)
+        .map(|(x, y)| to_color([x[0].hypot(y[0]), x[1].hypot(y[1]), x[2].hypot(y[2])]))
+        .collect();
+    Screen { w: screen.w, h: screen.h, colors }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Preset {
+    Box(usize),
+    Gaussian(f64),
+    Sharpen,
+    Emboss,
+    SobelX,
+    SobelY,
+    Sobel,
+}
+
+impl Preset {
+    pub fn apply(self, screen: &Screen, edge: EdgeMode, accumulator: Accumulator) -> Screen {
+        let kernel = match self {
+            Preset::Box(radius) => Kernel::box_blur(radius),
+            Preset::Gaussian(sigma) => Kernel::gaussian(sigma),
+            Preset::Sharpen => Kernel::sharpen(),
+            Preset::Emboss => Kernel::emboss(),
+            // signed gradients, gray where nothing changes
+            Preset::SobelX => Kernel::sobel_x().with_bias(128.0),
+            Preset::SobelY => Kernel::sobel_y().with_bias(128.0),
+            Preset::Sobel => return sobel(screen, edge, accumulator),
+        };
+        convolve(screen, &kernel, edge, accumulator)
+    }
+}
+
+// box:RADIUS, gaussian:SIGMA, sharpen, emboss, sobel, sobel-x or sobel-y
+impl std::str::FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid preset {:?}", s);
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+        match (name, parameter) {
+            ("box", p) => Ok(Preset::Box(p.map_or(Ok(1), str::parse).map_err(|_| invalid())?)),
+            ("gaussian", p) => {
+                let sigma: f64 = p.map_or(Ok(1.0), str::parse).map_err(|_| invalid())?;
+                if sigma > 0.0 {
+                    Ok(Preset::Gaussian(sigma))
+                } else {
+                    Err(invalid())
+                }
+            }
+            ("sharpen", None) => Ok(Preset::Sharpen),
+            ("emboss", None) => Ok(Preset::Emboss),
+            ("sobel", None) => Ok(Preset::Sobel),
+            ("sobel-x", None) => Ok(Preset::SobelX),
+            ("sobel-y", None) => Ok(Preset::SobelY),
+            _ => Err(format!(
+                "unknown preset {:?}, expected box:RADIUS, gaussian:SIGMA, sharpen, emboss, sobel, sobel-x or sobel-y",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(w: usize, h: usize, values: &[u8]) -> Screen {
+        Screen { w, h, colors: values.iter().map(|&v| (v, v, v)).collect() }
+    }
+
+    fn reds(screen: &Screen) -> Vec<u8> {
+        screen.colors.iter().map(|c| c.0).collect()
+    }
+
+    // 5 x 4, every channel different
+    fn pattern() -> Screen {
+        let colors = (0..20u8).map(|i| (i.wrapping_mul(13), 255 - i * 11, (i % 7) * 30)).collect();
+        Screen { w: 5, h: 4, colors }
+    }
+
+    #[test]
+    fn source_indices_at_the_edges() {
+        assert_eq!(source_index(-1, 4, EdgeMode::Clamp), Some(0));
+        assert_eq!(source_index(6, 4, EdgeMode::Clamp), Some(3));
+        assert_eq!(source_index(-1, 4, EdgeMode::Wrap), Some(3));
+        assert_eq!(source_index(9, 4, EdgeMode::Wrap), Some(1));
+        assert_eq!(source_index(-1, 4, EdgeMode::Mirror), Some(1));
+        assert_eq!(source_index(4, 4, EdgeMode::Mirror), Some(2));
+        assert_eq!(source_index(-3, 4, EdgeMode::Mirror), Some(3));
+        assert_eq!(source_index(7, 4, EdgeMode::Mirror), Some(1));
+        assert_eq!(source_index(-2, 1, EdgeMode::Mirror), Some(0));
+        assert_eq!(source_index(-1, 4, EdgeMode::Skip), None);
+        assert_eq!(source_index(2, 4, EdgeMode::Skip), Some(2));
+    }
+
+    #[test]
+    fn edge_modes() {
+        let screen = gray(3, 1, &[10, 20, 40]);
+        let kernel = Kernel::new(3, 1, vec![1.0, 1.0, 1.0]);
+        let expected = [
+            (EdgeMode::Clamp, [40, 70, 100]),
+            (EdgeMode::Wrap, [70, 70, 70]),
+            (EdgeMode::Mirror, [50, 70, 80]),
+            // 30 and 60 from two of three taps
+            (EdgeMode::Skip, [45, 70, 90]),
+        ];
+        for (edge, expected) in expected {
+            for accumulator in [Accumulator::Float, Accumulator::Fixed] {
+                assert_eq!(reds(&convolve(&screen, &kernel, edge, accumulator)), expected, "{:?} {:?}", edge, accumulator);
+            }
+        }
+    }
+
+    #[test]
+    fn skip_does_not_renormalize_zero_sum_kernels() {
+        let screen = gray(3, 1, &[10, 20, 40]);
+        let kernel = Kernel::new(3, 1, vec![-1.0, 0.0, 1.0]).with_bias(100.0);
+        assert_eq!(reds(&convolve(&screen, &kernel, EdgeMode::Skip, Accumulator::Float)), [120, 130, 80]);
+    }
+
+    #[test]
+    fn separable_kernels_match_their_full_product() {
+        let screen = pattern();
+        let kernels = [
+            (vec![1.0, 2.0, 1.0], vec![-1.0, 0.0, 1.0]),
+            (vec![0.25, 0.5, 0.25], vec![0.1, 0.2, 0.4, 0.2, 0.1]),
+        ];
+        for (column, row) in kernels {
+            let separable = Kernel::separable(column, row);
+            assert!(separable.is_separable());
+            let full = Kernel::new(separable.width, separable.height, separable.weights.clone());
+            assert!(!full.is_separable());
+            for edge in [EdgeMode::Clamp, EdgeMode::Wrap, EdgeMode::Mirror, EdgeMode::Skip] {
+                let a = raw(&screen, &separable, edge, Accumulator::Float);
+                let b = raw(&screen, &full, edge, Accumulator::Float);
+                for (a, b) in a.iter().zip(&b) {
+                    for c in 0..3 {
+                        assert!((a[c] - b[c]).abs() < 1e-9, "{:?}: {:?} != {:?}", edge, a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_point_is_exact_for_binary_fractions() {
+        let screen = gray(3, 1, &[0, 100, 200]);
+        let kernel = Kernel::new(3, 1, vec![0.25, 0.5, 0.25]);
+        let float = raw(&screen, &kernel, EdgeMode::Clamp, Accumulator::Float);
+        let fixed = raw(&screen, &kernel, EdgeMode::Clamp, Accumulator::Fixed);
+        assert_eq!(float, fixed);
+        assert_eq!(float.iter().map(|v| v[0]).collect::<Vec<_>>(), [25.0, 100.0, 175.0]);
+    }
+
+    #[test]
+    fn fixed_point_stays_close_to_float() {
+        let screen = pattern();
+        for kernel in [Kernel::box_blur(1), Kernel::gaussian(1.0), Kernel::sharpen(), Kernel::emboss()] {
+            for edge in [EdgeMode::Clamp, EdgeMode::Skip] {
+                let float = raw(&screen, &kernel, edge, Accumulator::Float);
+                let fixed = raw(&screen, &kernel, edge, Accumulator::Fixed);
+                for (a, b) in float.iter().zip(&fixed) {
+                    for c in 0..3 {
+                        // every tap loses less than one unit in the last of 16 fraction bits
+                        assert!((a[c] - b[c]).abs() < 0.01, "{:?} != {:?}", a, b);
+                    }
+                }
+                let float = convolve(&screen, &kernel, edge, Accumulator::Float);
+                let fixed = convolve(&screen, &kernel, edge, Accumulator::Fixed);
+                for (a, b) in float.colors.iter().zip(&fixed.colors) {
+                    assert!(a.0.abs_diff(b.0) <= 1 && a.1.abs_diff(b.1) <= 1 && a.2.abs_diff(b.2) <= 1);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,574 @@
+use std::sync::{Arc, OnceLock};
+
+use crate::canvas::Screen;
+use crate::primitive::{average, parse_color, Pixel, Rect};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: Vec<(u8, u8, u8)>,
+}
+
+fn channel(color: (u8, u8, u8), i: usize) -> u8 {
+    match i {
+        0 => color.0,
+        1 => color.1,
+        _ => color.2,
+    }
+}
+
+fn distance(a: (i32, i32, i32), b: (u8, u8, u8)) -> i32 {
+    let d = (a.0 - b.0 as i32, a.1 - b.1 as i32, a.2 - b.2 as i32);
+    d.0 * d.0 + d.1 * d.1 + d.2 * d.2
+}
+
+// channel with the largest spread and that spread
+fn widest_channel(colors: &[(u8, u8, u8)]) -> (usize, u8) {
+    (0..3)
+        .map(|i| {
+            let min = colors.iter().map(|&c| channel(c, i)).min().unwrap_or(0);
+            let max = colors.iter().map(|&c| channel(c, i)).max().unwrap_or(0);
+            (i, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+impl Palette {
+    pub fn new(colors: Vec<(u8, u8, u8)>) -> Self {
+        assert!(!colors.is_empty(), "empty palette");
+        Self { colors }
+    }
+
+    pub fn black_and_white() -> Self {
+        Self::new(vec![(0, 0, 0), (255, 255, 255)])
+    }
+
+    // takes unclamped colors, as they come out of error diffusion
+    pub fn nearest(&self, color: (i32, i32, i32)) -> (u8, u8, u8) {
+        *self.colors.iter().min_by_key(|&&c| distance(color, c)).unwrap()
+    }
+
+    // splits the box with the widest channel at its median until there are n boxes
+    pub fn median_cut(colors: &[(u8, u8, u8)], n: usize) -> Self {
+        if colors.is_empty() {
+            return Self::black_and_white();
+        }
+        let mut boxes: Vec<Vec<(u8, u8, u8)>> = vec![colors.to_vec()];
+        while boxes.len() < n {
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .map(|(i, b)| (i, widest_channel(b)))
+                .max_by_key(|&(_, (_, range))| range);
+            let (i, channel_index) = match widest {
+                Some((i, (channel_index, range))) if range > 0 => (i, channel_index),
+                // every box holds a single color
+                _ => break,
+            };
+            let mut lower = boxes.swap_remove(i);
+            lower.sort_unstable_by_key(|&c| channel(c, channel_index));
+            let upper = lower.split_off(lower.len() / 2);
+            boxes.push(lower);
+            boxes.push(upper);
+        }
+        Self::new(boxes.iter().map(|b| average(b)).collect())
+    }
+
+    // lloyd iterations, starting from the median cut palette
+    pub fn k_means(colors: &[(u8, u8, u8)], n: usize, iterations: usize) -> Self {
+        let mut palette = Self::median_cut(colors, n);
+        for _ in 0..iterations {
+            let mut sums = vec![(0u64, 0u64, 0u64, 0u64); palette.colors.len()];
+            for &c in colors {
+                let color = (c.0 as i32, c.1 as i32, c.2 as i32);
+                let (nearest, _) = palette
+                    .colors
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|&(_, &p)| distance(color, p))
+                    .unwrap();
+                let sum = &mut sums[nearest];
+                *sum = (sum.0 + c.0 as u64, sum.1 + c.1 as u64, sum.2 + c.2 as u64, sum.3 + 1);
+            }
+            let mut changed = false;
+            for (center, sum) in palette.colors.iter_mut().zip(&sums) {
+                // clusters that lost all their colors keep their center
+                if sum.3 == 0 {
+                    continue;
+                }
+                let mean = ((sum.0 / sum.3) as u8, (sum.1 / sum.3) as u8, (sum.2 / sum.3) as u8);
+                changed |= *center != mean;
+                *center = mean;
+            }
+            if !changed {
+                break;
+            }
+        }
+        palette
+    }
+}
+
+// RRGGBB,RRGGBB,...
+impl std::str::FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colors = s.split(',').map(|c| parse_color(c.trim())).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(colors))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Quantizer {
+    MedianCut,
+    KMeans,
+}
+
+impl Quantizer {
+    pub fn palette(self, colors: &[(u8, u8, u8)], n: usize) -> Palette {
+        match self {
+            Quantizer::MedianCut => Palette::median_cut(colors, n),
+            Quantizer::KMeans => Palette::k_means(colors, n, 16),
+        }
+    }
+}
+
+impl std::str::FromStr for Quantizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "median-cut" => Ok(Quantizer::MedianCut),
+            "k-means" => Ok(Quantizer::KMeans),
+            _ => Err(format!("unknown quantizer {:?}, expected median-cut or k-means", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kernel {
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Stucki,
+    Sierra,
+}
+
+impl Kernel {
+    // (dx, dy, weight) for the pixels after the current one, and the divisor
+    fn weights(self) -> (&'static [(i32, i32, i32)], i32) {
+        match self {
+            /*        *  7
+             *   3    5  1   / 16
+             */
+            Kernel::FloydSteinberg => (&[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)], 16),
+            // only 6/8 of the error is passed on, which keeps more contrast
+            Kernel::Atkinson => (&[(1, 0, 1), (2, 0, 1), (-1, 1, 1), (0, 1, 1), (1, 1, 1), (0, 2, 1)], 8),
+            Kernel::JarvisJudiceNinke => (&[
+                (1, 0, 7), (2, 0, 5),
+                (-2, 1, 3), (-1, 1, 5), (0, 1, 7), (1, 1, 5), (2, 1, 3),
+                (-2, 2, 1), (-1, 2, 3), (0, 2, 5), (1, 2, 3), (2, 2, 1),
+            ], 48),
+            Kernel::Stucki => (&[
+                (1, 0, 8), (2, 0, 4),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+                (-2, 2, 1), (-1, 2, 2), (0, 2, 4), (1, 2, 2), (2, 2, 1),
+            ], 42),
+            Kernel::Sierra => (&[
+                (1, 0, 5), (2, 0, 3),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+                (-1, 2, 2), (0, 2, 3), (1, 2, 2),
+            ], 32),
+        }
+    }
+}
+
+impl std::str::FromStr for Kernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "floyd-steinberg" => Ok(Kernel::FloydSteinberg),
+            "atkinson" => Ok(Kernel::Atkinson),
+            "jarvis-judice-ninke" | "jjn" => Ok(Kernel::JarvisJudiceNinke),
+            "stucki" => Ok(Kernel::Stucki),
+            "sierra" => Ok(Kernel::Sierra),
+            _ => Err(format!(
+                "unknown kernel {:?}, expected floyd-steinberg, atkinson, jarvis-judice-ninke, stucki or sierra",
+                s
+            )),
+        }
+    }
+}
+
+// quantizes the screen to the palette and spreads the error over the neighbors
+// given by the kernel, serpentine scanning walks every other row right to left
+pub fn diffuse(screen: &mut Screen, palette: &Palette, kernel: Kernel, serpentine: bool) {
+    let (weights, divisor) = kernel.weights();
+    let (w, h) = (screen.w, screen.h);
+    // floats, so small errors aren't rounded away by the divisor
+    let mut work: Vec<(f32, f32, f32)> = screen.colors.iter().map(|c| (c.0 as f32, c.1 as f32, c.2 as f32)).collect();
+    for y in 0..h {
+        let reverse = serpentine && y % 2 == 1;
+        for i in 0..w {
+            let x = if reverse { w - 1 - i } else { i };
+            let old = work[y * w + x];
+            // clamp so runaway errors don't pile up in saturated areas
+            let old = (old.0.clamp(0.0, 255.0), old.1.clamp(0.0, 255.0), old.2.clamp(0.0, 255.0));
+            let new = palette.nearest((old.0.round() as i32, old.1.round() as i32, old.2.round() as i32));
+            screen.colors[y * w + x] = new;
+            let error = (old.0 - new.0 as f32, old.1 - new.1 as f32, old.2 - new.2 as f32);
+            for &(dx, dy, weight) in weights {
+                let dx = if reverse { -dx } else { dx };
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if nx < 0 || nx >= w as i32 || ny >= h as i32 {
+                    continue;
+                }
+                let share = weight as f32 / divisor as f32;
+                let target = &mut work[ny as usize * w + nx as usize];
+                target.0 += error.0 * share;
+                target.1 += error.1 * share;
+                target.2 += error.2 * share;
+            }
+        }
+    }
+}
+
+// void and cluster (Ulichney 1993): pixels are ranked by repeatedly filling the
+// largest void of a gaussian-blurred binary pattern on a torus
+fn void_and_cluster(size: usize, seed: u64) -> Vec<usize> {
+    let n = size * size;
+    let sigma = 1.5;
+    let mut kernel = vec![0.0; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let ddx = dx.min(size - dx) as f64;
+            let ddy = dy.min(size - dy) as f64;
+            kernel[dy * size + dx] = (-(ddx * ddx + ddy * ddy) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+    let toggle = |energy: &mut [f64], p: usize, sign: f64| {
+        let (px, py) = (p % size, p / size);
+        for y in 0..size {
+            for x in 0..size {
+                let k = ((y + size - py) % size) * size + (x + size - px) % size;
+                energy[y * size + x] += sign * kernel[k];
+            }
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+        (0..n).filter(|&i| pattern[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| {
+        (0..n).filter(|&i| !pattern[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+
+    // random initial pattern with about a tenth of the pixels set
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = (n / 10).max(1);
+    while pattern.iter().filter(|&&p| p).count() < initial {
+        let p = rng.usize(0..n);
+        if !pattern[p] {
+            pattern[p] = true;
+            toggle(&mut energy, p, 1.0);
+        }
+    }
+    // move points from clusters into voids until that changes nothing
+    for _ in 0..n {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    // the initial points get the lowest ranks, tightest clusters last
+    let (mut phase1_pattern, mut phase1_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&phase1_pattern, &phase1_energy);
+        phase1_pattern[cluster] = false;
+        toggle(&mut phase1_energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+    // the rest in the order that fills the largest voids
+    for rank in initial..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks
+}
+
+fn thresholds(size: usize, ranks: &[usize]) -> Vec<f64> {
+    let n = (size * size) as f64;
+    ranks.iter().map(|&r| (r as f64 + 0.5) / n).collect()
+}
+
+// thresholds in [0, 1) that repeat every size pixels in both directions
+#[derive(Debug, Clone)]
+pub struct ThresholdMap {
+    size: usize,
+    // blue noise takes a while, it is generated when the first threshold is needed
+    // and then shared by all clones
+    blue_noise_seed: Option<u64>,
+    values: Arc<OnceLock<Vec<f64>>>,
+}
+
+impl ThresholdMap {
+    fn from_ranks(size: usize, ranks: &[usize]) -> Self {
+        Self { size, blue_noise_seed: None, values: Arc::new(OnceLock::from(thresholds(size, ranks))) }
+    }
+
+    // 2^order x 2^order, every step replaces each entry m with the 2x2 block 4m + [0 2; 3 1]
+    pub fn bayer(order: u32) -> Self {
+        let mut size = 1;
+        let mut ranks = vec![0usize];
+        for _ in 0..order {
+            let next_size = size * 2;
+            let mut next = vec![0; next_size * next_size];
+            for y in 0..next_size {
+                for x in 0..next_size {
+                    let offset = [[0, 2], [3, 1]][y / size][x / size];
+                    next[y * next_size + x] = 4 * ranks[(y % size) * size + x % size] + offset;
+                }
+            }
+            size = next_size;
+            ranks = next;
+        }
+        Self::from_ranks(size, &ranks[..])
+    }
+
+    pub fn blue_noise(size: usize, seed: u64) -> Self {
+        Self { size, blue_noise_seed: Some(seed), values: Arc::default() }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn threshold(&self, x: usize, y: usize) -> f64 {
+        let values = self.values.get_or_init(|| {
+            let seed = self.blue_noise_seed.unwrap_or_default();
+            thresholds(self.size, &void_and_cluster(self.size, seed)[..])
+        });
+        values[(y % self.size) * self.size + x % self.size]
+    }
+}
+
+// bayer2, bayer4, bayer8, bayer16 or blue-noise
+impl std::str::FromStr for ThresholdMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bayer2" => Ok(Self::bayer(1)),
+            "bayer4" => Ok(Self::bayer(2)),
+            "bayer8" => Ok(Self::bayer(3)),
+            "bayer16" => Ok(Self::bayer(4)),
+            "blue-noise" => Ok(Self::blue_noise(64, 0)),
+            _ => Err(format!(
+                "unknown threshold map {:?}, expected bayer2, bayer4, bayer8, bayer16 or blue-noise",
+                s
+            )),
+        }
+    }
+}
+
+// every output color only depends on the input color and its coordinates,
+// so pixels can be dithered in any order
+#[derive(Debug, Clone)]
+pub struct OrderedDither {
+    map: ThresholdMap,
+    palette: Palette,
+    spread: f64,
+}
+
+impl OrderedDither {
+    // the spread matches the distance between levels of an evenly spaced palette
+    // with the same number of colors
+    pub fn new(map: ThresholdMap, palette: Palette) -> Self {
+        let levels = (palette.colors.len() as f64).cbrt();
+        let spread = 255.0 / (levels - 1.0).max(1.0);
+        Self { map, palette, spread }
+    }
+
+    pub fn with_spread(mut self, spread: f64) -> Self {
+        self.spread = spread;
+        self
+    }
+
+    pub fn color(&self, x: usize, y: usize, color: (u8, u8, u8)) -> (u8, u8, u8) {
+        let offset = ((self.map.threshold(x, y) - 0.5) * self.spread).round() as i32;
+        self.palette.nearest((color.0 as i32 + offset, color.1 as i32 + offset, color.2 as i32 + offset))
+    }
+
+    pub fn pixel(&self, px: &Pixel) -> Pixel {
+        Pixel { x: px.x, y: px.y, color: self.color(px.x, px.y, px.color) }
+    }
+
+    // the screen holds the colors of rect, the pattern stays aligned to the server's coordinates
+    pub fn apply(&self, screen: &mut Screen, rect: Rect) {
+        for y in 0..screen.h {
+            for x in 0..screen.w {
+                let i = y * screen.w + x;
+                screen.colors[i] = self.color(rect.x + x, rect.y + y, screen.colors[i]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranks(map: &ThresholdMap) -> Vec<usize> {
+        let n = (map.size() * map.size()) as f64;
+        (0..map.size())
+            .flat_map(|y| (0..map.size()).map(move |x| (x, y)))
+            .map(|(x, y)| (map.threshold(x, y) * n - 0.5).round() as usize)
+            .collect()
+    }
+
+    fn is_permutation(ranks: &[usize]) -> bool {
+        let mut sorted = ranks.to_vec();
+        sorted.sort_unstable();
+        sorted.iter().copied().eq(0..ranks.len())
+    }
+
+    const KERNELS: [Kernel; 5] = [
+        Kernel::FloydSteinberg,
+        Kernel::Atkinson,
+        Kernel::JarvisJudiceNinke,
+        Kernel::Stucki,
+        Kernel::Sierra,
+    ];
+
+    fn flat(w: usize, h: usize, color: (u8, u8, u8)) -> Screen {
+        Screen { w, h, colors: vec![color; w * h] }
+    }
+
+    fn mean_red(screen: &Screen) -> f64 {
+        screen.colors.iter().map(|c| c.0 as f64).sum::<f64>() / screen.colors.len() as f64
+    }
+
+    #[test]
+    fn kernels_only_reach_pixels_that_come_later() {
+        for kernel in KERNELS {
+            let (weights, divisor) = kernel.weights();
+            assert!(weights.iter().all(|&(dx, dy, _)| dy > 0 || (dy == 0 && dx > 0)), "{:?}", kernel);
+            let total: i32 = weights.iter().map(|w| w.2).sum();
+            // atkinson drops a quarter of the error on purpose
+            let expected = if kernel == Kernel::Atkinson { 6 } else { divisor };
+            assert_eq!(total, expected, "{:?}", kernel);
+        }
+    }
+
+    #[test]
+    fn diffusion_preserves_the_mean_of_flat_grays() {
+        let palette = Palette::black_and_white();
+        for kernel in KERNELS {
+            for serpentine in [false, true] {
+                for gray in [128, 64, 200] {
+                    // atkinson loses some of the error, so only mid gray stays as it is
+                    if kernel == Kernel::Atkinson && gray != 128 {
+                        continue;
+                    }
+                    let mut screen = flat(64, 64, (gray, gray, gray));
+                    diffuse(&mut screen, &palette, kernel, serpentine);
+                    assert!(screen.colors.iter().all(|c| palette.colors.contains(c)));
+                    // only the error that falls off the edges is lost
+                    let mean = mean_red(&screen);
+                    assert!((mean - gray as f64).abs() < 3.0, "{:?} {} {}: {}", kernel, serpentine, gray, mean);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn small_errors_add_up() {
+        // an error of 4 is less than one in every tap of floyd-steinberg
+        let mut screen = flat(256, 256, (4, 4, 4));
+        diffuse(&mut screen, &Palette::black_and_white(), Kernel::FloydSteinberg, false);
+        let mean = mean_red(&screen);
+        assert!((mean - 4.0).abs() < 0.5, "{}", mean);
+    }
+
+    #[test]
+    fn median_cut_returns_at_most_n_colors() {
+        let colors: Vec<(u8, u8, u8)> = (0..=255).map(|i| (i, 255 - i, i / 2)).collect();
+        for n in [1, 2, 5, 16] {
+            assert_eq!(Palette::median_cut(&colors[..], n).colors.len(), n);
+        }
+        // boxes with a single color aren't split any further
+        let three = [(1, 2, 3), (200, 0, 0), (0, 0, 200)];
+        let mut palette = Palette::median_cut(&three[..], 8).colors;
+        palette.sort_unstable();
+        assert_eq!(palette, [(0, 0, 200), (1, 2, 3), (200, 0, 0)]);
+        assert_eq!(Palette::median_cut(&[], 4), Palette::black_and_white());
+    }
+
+    #[test]
+    fn k_means_converges_on_two_colors() {
+        fastrand::seed(3);
+        let jitter = || fastrand::u8(0..9);
+        let colors: Vec<(u8, u8, u8)> = (0..400)
+            .map(|i| if i % 3 == 0 { (200 + jitter(), 40 + jitter(), 40) } else { (10 + jitter(), 10, 100 + jitter()) })
+            .collect();
+        let mean = |dark: bool| average(&colors.iter().copied().filter(|c| (c.0 < 100) == dark).collect::<Vec<_>>()[..]);
+        let mut palette = Palette::k_means(&colors[..], 2, 16).colors;
+        palette.sort_unstable();
+        assert_eq!(palette, [mean(true), mean(false)]);
+    }
+
+    #[test]
+    fn bayer_matrices_have_the_standard_ranks() {
+        assert_eq!(ranks(&ThresholdMap::bayer(0)), [0]);
+        assert_eq!(ranks(&ThresholdMap::bayer(1)), [0, 2, 3, 1]);
+        assert_eq!(ranks(&ThresholdMap::bayer(2)), [
+            0, 8, 2, 10,
+            12, 4, 14, 6,
+            3, 11, 1, 9,
+            15, 7, 13, 5,
+        ]);
+        for order in 0..=4 {
+            let map = ThresholdMap::bayer(order);
+            assert_eq!(map.size(), 1 << order);
+            assert!(is_permutation(&ranks(&map)[..]), "order {}", order);
+        }
+    }
+
+    #[test]
+    fn blue_noise_ranks_are_unique() {
+        for (size, seed) in [(4, 1), (8, 2), (16, 3)] {
+            let map = ThresholdMap::blue_noise(size, seed);
+            assert_eq!(map.size(), size);
+            assert!(is_permutation(&ranks(&map)[..]), "{}x{} seed {}", size, size, seed);
+        }
+    }
+
+    #[test]
+    fn thresholds_repeat_across_the_screen() {
+        let map = ThresholdMap::bayer(2);
+        assert_eq!(map.threshold(1, 2), map.threshold(5, 10));
+        assert_eq!(map.threshold(0, 0), 0.5 / 16.0);
+    }
+
+    #[test]
+    fn blue_noise_is_generated_on_first_use() {
+        let map: ThresholdMap = "blue-noise".parse().unwrap();
+        assert_eq!(map.size(), 64);
+        assert!(map.values.get().is_none());
+        // clones share the map once it was generated
+        let copy = map.clone();
+        let threshold = copy.threshold(3, 4);
+        assert_eq!(map.values.get().map(|values| values[4 * 64 + 3]), Some(threshold));
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::primitive::Rect;
+
+#[derive(Debug)]
+pub enum PixelflutError {
+    Io(std::io::Error),
+    // the connection closed before the full response arrived
+    ShortRead { expected: usize, received: usize },
+    // coordinates that can not be encoded in the wire format
+    OutOfRange { x: usize, y: usize },
+    // rectangle dimensions that can not be encoded in the wire format
+    RectTooLarge(Rect),
+    // the color buffer does not match the rectangle size
+    ColorCount { expected: usize, actual: usize },
+    MalformedInfo(String),
+    MalformedResponse(String),
+    // the command is not available with the selected protocol
+    Unsupported(&'static str),
+    // an image file in a format or variant we can not decode
+    Decode(String),
+}
+
+pub type Result<T> = std::result::Result<T, PixelflutError>;
+
+impl PixelflutError {
+    // errors after which the connection is unusable, but reconnecting may help
+    pub fn is_connection_error(&self) -> bool {
+        matches!(self, PixelflutError::Io(_) | PixelflutError::ShortRead { .. })
+    }
+}
+
+impl fmt::Display for PixelflutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PixelflutError::Io(e) => write!(f, "i/o error: {}", e),
+            PixelflutError::ShortRead { expected, received } => {
+                write!(f, "short read: expected {} bytes, received {}", expected, received)
+            }
+            PixelflutError::OutOfRange { x, y } => write!(f, "coordinates ({}, {}) out of range", x, y),
+            PixelflutError::RectTooLarge(rect) => {
+                write!(f, "rectangle {}x{} at ({}, {}) too large", rect.w, rect.h, rect.x, rect.y)
+            }
+            PixelflutError::ColorCount { expected, actual } => {
+                write!(f, "expected {} colors for rectangle, got {}", expected, actual)
+            }
+            PixelflutError::MalformedInfo(s) => write!(f, "malformed server info: {}", s),
+            PixelflutError::MalformedResponse(s) => write!(f, "malformed response: {:?}", s),
+            PixelflutError::Unsupported(command) => write!(f, "{} is not supported by this protocol", command),
+            PixelflutError::Decode(s) => write!(f, "can not decode image: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for PixelflutError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PixelflutError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PixelflutError {
+    fn from(e: std::io::Error) -> Self {
+        PixelflutError::Io(e)
+    }
+}
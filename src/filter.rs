@@ -0,0 +1,536 @@
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use crate::canvas::Screen;
+use crate::client::PixelflutClient;
+use crate::convolve::{self, Accumulator, EdgeMode, Kernel, Preset};
+use crate::error::Result;
+use crate::primitive::{average, Rect};
+use crate::protocol::Protocol;
+
+pub trait Filter: Debug {
+    fn apply(&self, screen: &Screen) -> Screen;
+}
+
+fn luminance(c: (u8, u8, u8)) -> f64 {
+    0.299 * c.0 as f64 + 0.587 * c.1 as f64 + 0.114 * c.2 as f64
+}
+
+// colors of the square with the given radius around (x, y), cut off at the edges
+fn window(screen: &Screen, x: usize, y: usize, radius: usize) -> impl Iterator<Item = (u8, u8, u8)> + '_ {
+    let xs = x.saturating_sub(radius)..(x + radius + 1).min(screen.w);
+    let ys = y.saturating_sub(radius)..(y + radius + 1).min(screen.h);
+    ys.flat_map(move |yy| xs.clone().map(move |xx| screen.colors[yy * screen.w + xx]))
+}
+
+// largest radius the window filters accept from a pipeline string
+pub const MAX_RADIUS: usize = 32;
+
+fn map_windows<F: Fn(&mut Vec<(u8, u8, u8)>) -> (u8, u8, u8)>(screen: &Screen, radius: usize, f: F) -> Screen {
+    // larger windows contain the whole screen anyway
+    let radius = radius.min(screen.w.max(screen.h));
+    let mut samples = Vec::with_capacity((2 * radius + 1) * (2 * radius + 1));
+    let mut colors = Vec::with_capacity(screen.w * screen.h);
+    for y in 0..screen.h {
+        for x in 0..screen.w {
+            samples.clear();
+            samples.extend(window(screen, x, y, radius));
+            colors.push(f(&mut samples));
+        }
+    }
+    Screen { w: screen.w, h: screen.h, colors }
+}
+
+fn channel_median(samples: &mut [(u8, u8, u8)], channel: fn(&(u8, u8, u8)) -> u8) -> u8 {
+    let mid = samples.len() / 2;
+    samples.select_nth_unstable_by_key(mid, channel);
+    channel(&samples[mid])
+}
+
+// median of every channel on its own, removes salt and pepper noise but keeps edges
+#[derive(Debug, Copy, Clone)]
+pub struct Median {
+    pub radius: usize,
+}
+
+impl Filter for Median {
+    fn apply(&self, screen: &Screen) -> Screen {
+        map_windows(screen, self.radius, |samples| {
+            (
+                channel_median(samples, |c| c.0),
+                channel_median(samples, |c| c.1),
+                channel_median(samples, |c| c.2),
+            )
+        })
+    }
+}
+
+// per-channel minimum, dark areas grow
+#[derive(Debug, Copy, Clone)]
+pub struct Erode {
+    pub radius: usize,
+}
+
+impl Filter for Erode {
+    fn apply(&self, screen: &Screen) -> Screen {
+        map_windows(screen, self.radius, |samples| {
+            samples.iter().fold((255, 255, 255), |m, c| (m.0.min(c.0), m.1.min(c.1), m.2.min(c.2)))
+        })
+    }
+}
+
+// per-channel maximum, bright areas grow
+#[derive(Debug, Copy, Clone)]
+pub struct Dilate {
+    pub radius: usize,
+}
+
+impl Filter for Dilate {
+    fn apply(&self, screen: &Screen) -> Screen {
+        map_windows(screen, self.radius, |samples| {
+            samples.iter().fold((0, 0, 0), |m, c| (m.0.max(c.0), m.1.max(c.1), m.2.max(c.2)))
+        })
+    }
+}
+
+// horizontal and vertical sobel responses of a luminance plane, edges are clamped
+fn gradients(plane: &[f64], w: usize, h: usize) -> Vec<(f64, f64)> {
+    let at = |x: isize, y: isize| plane[y.clamp(0, h as isize - 1) as usize * w + x.clamp(0, w as isize - 1) as usize];
+    let mut result = Vec::with_capacity(w * h);
+    for y in 0..h as isize {
+        for x in 0..w as isize {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1) - 2.0 * at(x - 1, y) - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1) - 2.0 * at(x, y - 1) - at(x + 1, y - 1);
+            result.push((gx, gy));
+        }
+    }
+    result
+}
+
+fn gray(w: usize, h: usize, values: impl Iterator<Item = f64>) -> Screen {
+    let colors = values
+        .map(|v| {
+            let v = v.round().clamp(0.0, 255.0) as u8;
+            (v, v, v)
+        })
+        .collect();
+    Screen { w, h, colors }
+}
+
+// gradient magnitude of the luminance as a gray edge map
+#[derive(Debug, Copy, Clone)]
+pub struct Sobel;
+
+impl Filter for Sobel {
+    fn apply(&self, screen: &Screen) -> Screen {
+        let plane: Vec<f64> = screen.colors.iter().map(|&c| luminance(c)).collect();
+        let g = gradients(&plane[..], screen.w, screen.h);
+        gray(screen.w, screen.h, g.iter().map(|(gx, gy)| gx.hypot(*gy)))
+    }
+}
+
+// thin white edges on black, thresholds are fractions of the strongest gradient
+#[derive(Debug, Copy, Clone)]
+pub struct Canny {
+    pub sigma: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Default for Canny {
+    fn default() -> Self {
+        Self { sigma: 1.4, low: 0.1, high: 0.25 }
+    }
+}
+
+impl Filter for Canny {
+    fn apply(&self, screen: &Screen) -> Screen {
+        let (w, h) = (screen.w, screen.h);
+        let luma = gray(w, h, screen.colors.iter().map(|&c| luminance(c)));
+        let blurred = convolve::convolve(&luma, &Kernel::gaussian(self.sigma), EdgeMode::Clamp, Accumulator::Float);
+        let plane: Vec<f64> = blurred.colors.iter().map(|c| c.0 as f64).collect();
+        let g = gradients(&plane[..], w, h);
+        let magnitude: Vec<f64> = g.iter().map(|(gx, gy)| gx.hypot(*gy)).collect();
+
+        // keep only pixels that are a maximum across the edge
+        let at = |x: isize, y: isize| {
+            if x < 0 || y < 0 || x >= w as isize || y >= h as isize {
+                0.0
+            } else {
+                magnitude[y as usize * w + x as usize]
+            }
+        };
+        let mut thin = vec![0.0; w * h];
+        for y in 0..h {
+            for x in 0..w {
+                let i = y * w + x;
+                let (gx, gy) = g[i];
+                let angle = gy.atan2(gx).to_degrees().rem_euclid(180.0);
+                let (dx, dy) = if !(22.5..157.5).contains(&angle) {
+                    (1, 0)
+                } else if angle < 67.5 {
+                    (1, 1)
+                } else if angle < 112.5 {
+                    (0, 1)
+                } else {
+                    (-1, 1)
+                };
+                let (xi, yi) = (x as isize, y as isize);
+                // strict on one side, so plateaus two pixels wide stay one pixel thick
+                if magnitude[i] > at(xi + dx, yi + dy) && magnitude[i] >= at(xi - dx, yi - dy) {
+                    thin[i] = magnitude[i];
+                }
+            }
+        }
+
+        // hysteresis: weak edges survive if they are connected to a strong one
+        let max = thin.iter().cloned().fold(0.0, f64::max);
+        let (low, high) = (self.low * max, self.high * max);
+        let mut edges = vec![false; w * h];
+        let mut stack: Vec<usize> = (0..w * h).filter(|&i| max > 0.0 && thin[i] >= high).collect();
+        for &i in &stack {
+            edges[i] = true;
+        }
+        while let Some(i) = stack.pop() {
+            let (x, y) = ((i % w) as isize, (i / w) as isize);
+            for (dx, dy) in [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= w as isize || ny >= h as isize {
+                    continue;
+                }
+                let n = ny as usize * w + nx as usize;
+                if !edges[n] && thin[n] >= low && thin[n] > 0.0 {
+                    edges[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+        gray(w, h, edges.iter().map(|&e| if e { 255.0 } else { 0.0 }))
+    }
+}
+
+// levels values per channel, evenly spread from 0 to 255
+#[derive(Debug, Copy, Clone)]
+pub struct Posterize {
+    pub levels: u8,
+}
+
+impl Filter for Posterize {
+    fn apply(&self, screen: &Screen) -> Screen {
+        let steps = self.levels.max(2) as u32 - 1;
+        let quantize = |v: u8| ((v as u32 * steps + 127) / 255 * 255 / steps) as u8;
+        let colors = screen.colors.iter().map(|c| (quantize(c.0), quantize(c.1), quantize(c.2))).collect();
+        Screen { w: screen.w, h: screen.h, colors }
+    }
+}
+
+fn fill_average(screen: &mut Screen, block: Rect) {
+    let colors: Vec<(u8, u8, u8)> = block.ys_abs()
+        .flat_map(|y| block.xs_abs().map(move |x| (x, y)))
+        .map(|(x, y)| screen.colors[y * screen.w + x])
+        .collect();
+    let color = average(&colors[..]);
+    for y in block.ys_abs() {
+        for x in block.xs_abs() {
+            screen.colors[y * screen.w + x] = color;
+        }
+    }
+}
+
+// averages a regular grid of size x size blocks
+#[derive(Debug, Copy, Clone)]
+pub struct Pixelate {
+    pub size: usize,
+}
+
+impl Filter for Pixelate {
+    fn apply(&self, screen: &Screen) -> Screen {
+        let mut result = Screen { w: screen.w, h: screen.h, colors: screen.colors.clone() };
+        for block in (Rect { x: 0, y: 0, w: screen.w, h: screen.h }).tiles(self.size, self.size) {
+            fill_average(&mut result, block);
+        }
+        result
+    }
+}
+
+// averages count randomly placed size x size squares, like the blur command does live
+#[derive(Debug, Copy, Clone)]
+pub struct Mosaic {
+    pub size: usize,
+    // None covers the area about twice
+    pub count: Option<usize>,
+}
+
+impl Filter for Mosaic {
+    fn apply(&self, screen: &Screen) -> Screen {
+        let mut result = Screen { w: screen.w, h: screen.h, colors: screen.colors.clone() };
+        let size = self.size.max(1);
+        let count = self.count.unwrap_or(2 * screen.w * screen.h / (size * size));
+        for _ in 0..count {
+            let w = size.min(screen.w);
+            let h = size.min(screen.h);
+            let x = fastrand::usize(0..=screen.w - w);
+            let y = fastrand::usize(0..=screen.h - h);
+            fill_average(&mut result, Rect { x, y, w, h });
+        }
+        result
+    }
+}
+
+// the linear presets, with clamped edges
+impl Filter for Preset {
+    fn apply(&self, screen: &Screen) -> Screen {
+        Preset::apply(*self, screen, EdgeMode::Clamp, Accumulator::Float)
+    }
+}
+
+// filters applied one after the other
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    filters: Vec<Arc<dyn Filter>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then<F: Filter + 'static>(mut self, filter: F) -> Self {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    // fetches rect, filters it and prints the result back
+    pub fn run<S: Read + Write, P: Protocol>(&self, client: &mut PixelflutClient<S, P>, rect: Rect) -> Result<()> {
+        let mut screen = Screen::new(rect.w, rect.h);
+        client.rectangle_get(&mut screen.colors[..], rect)?;
+        let result = self.apply(&screen);
+        client.rectangle_print(&result.colors[..], rect)
+    }
+}
+
+impl Filter for Pipeline {
+    fn apply(&self, screen: &Screen) -> Screen {
+        let mut result = Screen { w: screen.w, h: screen.h, colors: screen.colors.clone() };
+        for filter in &self.filters {
+            result = filter.apply(&result);
+        }
+        result
+    }
+}
+
+fn parameter<T: std::str::FromStr>(spec: &str, parameters: &[&str], i: usize, default: T) -> std::result::Result<T, String> {
+    match parameters.get(i) {
+        Some(p) => p.parse().map_err(|_| format!("invalid parameter {:?} in filter {:?}", p, spec)),
+        None => Ok(default),
+    }
+}
+
+fn radius(spec: &str, parameters: &[&str]) -> std::result::Result<usize, String> {
+    let radius = parameter(spec, parameters, 0, 1)?;
+    if radius > MAX_RADIUS {
+        return Err(format!("radius {} in filter {:?} is larger than {}", radius, spec, MAX_RADIUS));
+    }
+    Ok(radius)
+}
+
+// comma separated NAME[:PARAMETER...], e.g. median:2,posterize:4,pixelate:8
+impl std::str::FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut pipeline = Pipeline::new();
+        for spec in s.split(',').map(str::trim) {
+            let mut parts = spec.split(':');
+            let name = parts.next().unwrap_or("");
+            let p: Vec<&str> = parts.collect();
+            pipeline = match name {
+                "median" => pipeline.then(Median { radius: radius(spec, &p)? }),
+                "erode" => pipeline.then(Erode { radius: radius(spec, &p)? }),
+                "dilate" => pipeline.then(Dilate { radius: radius(spec, &p)? }),
+                "sobel" => pipeline.then(Sobel),
+                "canny" => {
+                    let default = Canny::default();
+                    pipeline.then(Canny {
+                        low: parameter(spec, &p, 0, default.low)?,
+                        high: parameter(spec, &p, 1, default.high)?,
+                        sigma: parameter(spec, &p, 2, default.sigma)?,
+                    })
+                }
+                "posterize" => pipeline.then(Posterize { levels: parameter(spec, &p, 0, 4)? }),
+                "pixelate" => pipeline.then(Pixelate { size: parameter(spec, &p, 0, 8)? }),
+                "mosaic" => pipeline.then(Mosaic {
+                    size: parameter(spec, &p, 0, 15)?,
+                    count: p.get(1).map(|_| parameter(spec, &p, 1, 0)).transpose()?,
+                }),
+                _ => pipeline.then(spec.parse::<Preset>().map_err(|_| format!(
+                    "unknown filter {:?}, expected median, erode, dilate, sobel, canny, posterize, pixelate, mosaic or a convolve preset",
+                    spec
+                ))?),
+            };
+        }
+        Ok(pipeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_screen(w: usize, h: usize, values: &[u8]) -> Screen {
+        Screen { w, h, colors: values.iter().map(|&v| (v, v, v)).collect() }
+    }
+
+    fn reds(screen: &Screen) -> Vec<u8> {
+        screen.colors.iter().map(|c| c.0).collect()
+    }
+
+    // a single white pixel in the middle of black
+    fn dot(w: usize, h: usize) -> Screen {
+        let mut screen = Screen::new(w, h);
+        screen.colors[h / 2 * w + w / 2] = (255, 255, 255);
+        screen
+    }
+
+    // left half black, right half gray
+    fn step(w: usize, h: usize, value: u8) -> Screen {
+        let values: Vec<u8> = (0..w * h).map(|i| if i % w < w / 2 { 0 } else { value }).collect();
+        gray_screen(w, h, &values[..])
+    }
+
+    #[test]
+    fn median_removes_noise_and_keeps_edges() {
+        assert_eq!(reds(&Median { radius: 1 }.apply(&dot(3, 3))), [0; 9]);
+        let edge = step(4, 3, 200);
+        assert_eq!(Median { radius: 1 }.apply(&edge).colors, edge.colors);
+        // every channel on its own
+        let screen = Screen { w: 3, h: 1, colors: vec![(1, 9, 5), (2, 8, 4), (3, 7, 6)] };
+        assert_eq!(Median { radius: 1 }.apply(&screen).colors[1], (2, 8, 5));
+    }
+
+    #[test]
+    fn erode_and_dilate() {
+        let dilated = Dilate { radius: 1 }.apply(&dot(5, 5));
+        assert_eq!(reds(&dilated), [
+            0, 0, 0, 0, 0,
+            0, 255, 255, 255, 0,
+            0, 255, 255, 255, 0,
+            0, 255, 255, 255, 0,
+            0, 0, 0, 0, 0,
+        ]);
+        assert_eq!(reds(&Erode { radius: 1 }.apply(&dilated)), reds(&dot(5, 5)));
+        assert_eq!(reds(&Erode { radius: 1 }.apply(&dot(5, 5))), [0; 25]);
+        // huge radii cover the whole screen
+        assert_eq!(reds(&Dilate { radius: usize::MAX }.apply(&dot(5, 5))), [255; 25]);
+    }
+
+    #[test]
+    fn sobel_responds_to_steps_only() {
+        assert_eq!(reds(&Sobel.apply(&gray_screen(3, 3, &[50; 9]))), [0; 9]);
+        // 1 + 2 + 1 times the step next to it, nothing where the clamped edge is flat
+        assert_eq!(reds(&Sobel.apply(&step(4, 2, 10))), [0, 40, 40, 0, 0, 40, 40, 0]);
+    }
+
+    fn edge_columns(screen: &Screen) -> Vec<Vec<usize>> {
+        (0..screen.h)
+            .map(|y| (0..screen.w).filter(|&x| screen.colors[y * screen.w + x] == (255, 255, 255)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn canny_finds_thin_edges() {
+        assert!(edge_columns(&Canny::default().apply(&gray_screen(8, 8, &[90; 64]))).iter().all(Vec::is_empty));
+        // one pixel wide, although the gradient spans two columns
+        let edges = edge_columns(&Canny::default().apply(&step(12, 6, 255)));
+        assert!(edges.iter().all(|row| row.len() == 1 && row[0] == edges[0][0]), "{:?}", edges);
+        assert!((5..=6).contains(&edges[0][0]));
+    }
+
+    // a vertical step that gets stronger towards the bottom, and a weak one on
+    // its own further right
+    fn weak_and_strong() -> Screen {
+        let (w, h) = (24, 24);
+        let values: Vec<u8> = (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                match x {
+                    0..=5 => 0,
+                    6..=15 => 40 + 9 * y as u8,
+                    _ => 9 * y as u8,
+                }
+            })
+            .collect();
+        gray_screen(w, h, &values[..])
+    }
+
+    #[test]
+    fn canny_keeps_weak_edges_connected_to_strong_ones() {
+        let near = |row: &Vec<usize>, x: usize| row.iter().any(|&e| e + 1 >= x && e <= x + 1);
+        let canny = Canny { sigma: 1.0, low: 0.1, high: 0.5 };
+        let edges = edge_columns(&canny.apply(&weak_and_strong()));
+        // the left edge survives along its full height, its weak top through the strong bottom
+        assert!(edges.iter().all(|row| near(row, 6)), "{:?}", edges);
+        // the free standing weak edge doesn't
+        assert!(edges.iter().all(|row| !near(row, 16)), "{:?}", edges);
+
+        // without hysteresis the weak top is gone as well
+        let edges = edge_columns(&Canny { low: 0.5, ..canny }.apply(&weak_and_strong()));
+        assert!(!near(&edges[0], 6) && near(&edges[23], 6), "{:?}", edges);
+
+        // and with a low enough high threshold the free standing edge stays
+        let edges = edge_columns(&Canny { high: 0.1, ..canny }.apply(&weak_and_strong()));
+        assert!(edges.iter().all(|row| near(row, 16)), "{:?}", edges);
+    }
+
+    #[test]
+    fn posterize_rounds_to_the_nearest_level() {
+        let screen = gray_screen(6, 1, &[0, 42, 43, 128, 212, 255]);
+        assert_eq!(reds(&Posterize { levels: 4 }.apply(&screen)), [0, 0, 85, 170, 170, 255]);
+        assert_eq!(reds(&Posterize { levels: 2 }.apply(&gray_screen(2, 1, &[127, 128]))), [0, 255]);
+        // fewer than two levels behave like two
+        assert_eq!(reds(&Posterize { levels: 0 }.apply(&gray_screen(2, 1, &[127, 128]))), [0, 255]);
+    }
+
+    #[test]
+    fn pixelate_averages_blocks() {
+        let screen = gray_screen(3, 2, &[0, 10, 100, 20, 30, 50]);
+        // the blocks at the right edge are cut off
+        assert_eq!(reds(&Pixelate { size: 2 }.apply(&screen)), [15, 15, 75, 15, 15, 75]);
+    }
+
+    #[test]
+    fn mosaic_squares_stay_on_the_screen() {
+        let screen = gray_screen(3, 2, &[0, 10, 100, 20, 30, 50]);
+        assert_eq!(reds(&Mosaic { size: 10, count: Some(1) }.apply(&screen)), [35; 6]);
+        assert_eq!(Mosaic { size: 2, count: Some(0) }.apply(&screen).colors, screen.colors);
+    }
+
+    #[test]
+    fn parses_pipelines() {
+        let pipeline: Pipeline = "median:2, posterize:4,pixelate,canny:0.2:0.4,gaussian:1.5".parse().unwrap();
+        assert_eq!(
+            format!("{:?}", pipeline.filters),
+            "[Median { radius: 2 }, Posterize { levels: 4 }, Pixelate { size: 8 }, \
+             Canny { sigma: 1.4, low: 0.2, high: 0.4 }, Gaussian(1.5)]"
+        );
+        assert!("erode".parse::<Pipeline>().is_ok());
+        assert!("mosaic:4:10".parse::<Pipeline>().is_ok());
+        for s in ["", "median:x", "median:33", "posterize:256", "blur", "median,", "canny:a"] {
+            assert!(s.parse::<Pipeline>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn pipelines_apply_filters_in_order() {
+        let pipeline = Pipeline::new().then(Dilate { radius: 1 }).then(Erode { radius: 1 });
+        assert_eq!(pipeline.apply(&dot(5, 5)).colors, dot(5, 5).colors);
+        assert_eq!(Pipeline::new().apply(&dot(3, 3)).colors, dot(3, 3).colors);
+    }
+}
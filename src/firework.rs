@@ -1,5 +1,8 @@
 use crate::primitive::Pixel;
 
+// added to the vertical speed of particles every step, y grows downwards
+pub const GRAVITY: f64 = 0.1;
+
 pub struct Particle {
     pub x: f64,
     pub y: f64,
@@ -28,7 +31,7 @@ impl Particle {
         } else {
             self.color.2 = ((self.color.2 as usize)*19/20) as u8;
         }
-        self.vy += 0.1;
+        self.vy += GRAVITY;
     }
 
     pub fn to_pixel(&self, xsize: usize, ysize: usize) -> Option<Pixel> {
@@ -36,7 +39,7 @@ impl Particle {
             None
         } else {
             let (x, y) = (self.x as usize, self.y as usize);
-            if x >= 1024 || y >= 1024 {
+            if x >= xsize || y >= ysize {
                 None
             } else {
                 if self.color == (0, 0, 0) {
@@ -87,12 +90,21 @@ impl Firework {
     }
 
     pub fn current_pixels(&self) -> Vec<Pixel> {
+        self.pixels(1024, 1024)
+    }
+
+    pub fn pixels(&self, xsize: usize, ysize: usize) -> Vec<Pixel> {
         let mut v = Vec::new();
         for p in &self.particles {
-            if let Some(px) = p.to_pixel(1024, 1024) {
+            if let Some(px) = p.to_pixel(xsize, ysize) {
                 v.push(px);
             }
         }
         v
     }
+
+    // every particle has faded out or fallen below ysize
+    pub fn is_done(&self, ysize: usize) -> bool {
+        self.particles.iter().all(|p| p.color == (0, 0, 0) || p.y >= ysize as f64)
+    }
 }
@@ -0,0 +1,412 @@
+use std::f64::consts::PI;
+
+use crate::canvas::Screen;
+use crate::gradient::Gradient;
+use crate::ifs::{pick, Affine, Point, WARMUP};
+
+// the nonlinear functions of the fractal flame paper, applied after the affine part
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Variation {
+    Linear,
+    Sinusoidal,
+    Spherical,
+    Swirl,
+    Horseshoe,
+    Polar,
+    Handkerchief,
+    Heart,
+    Disc,
+    Spiral,
+}
+
+const VARIATIONS: [Variation; 10] = [
+    Variation::Linear,
+    Variation::Sinusoidal,
+    Variation::Spherical,
+    Variation::Swirl,
+    Variation::Horseshoe,
+    Variation::Polar,
+    Variation::Handkerchief,
+    Variation::Heart,
+    Variation::Disc,
+    Variation::Spiral,
+];
+
+impl Variation {
+    pub fn apply(self, (x, y): Point) -> Point {
+        // keeps points at the origin from dividing by zero
+        let r2 = x * x + y * y + 1e-12;
+        let r = r2.sqrt();
+        // measured from the y axis, as in the paper
+        let theta = x.atan2(y);
+        match self {
+            Variation::Linear => (x, y),
+            Variation::Sinusoidal => (x.sin(), y.sin()),
+            Variation::Spherical => (x / r2, y / r2),
+            Variation::Swirl => (x * r2.sin() - y * r2.cos(), x * r2.cos() + y * r2.sin()),
+            Variation::Horseshoe => ((x - y) * (x + y) / r, 2.0 * x * y / r),
+            Variation::Polar => (theta / PI, r - 1.0),
+            Variation::Handkerchief => (r * (theta + r).sin(), r * (theta - r).cos()),
+            Variation::Heart => (r * (theta * r).sin(), -r * (theta * r).cos()),
+            Variation::Disc => (theta / PI * (PI * r).sin(), theta / PI * (PI * r).cos()),
+            Variation::Spiral => ((theta.cos() + r.sin()) / r, (theta.sin() - r.cos()) / r),
+        }
+    }
+}
+
+impl std::str::FromStr for Variation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Variation::Linear),
+            "sinusoidal" => Ok(Variation::Sinusoidal),
+            "spherical" => Ok(Variation::Spherical),
+            "swirl" => Ok(Variation::Swirl),
+            "horseshoe" => Ok(Variation::Horseshoe),
+            "polar" => Ok(Variation::Polar),
+            "handkerchief" => Ok(Variation::Handkerchief),
+            "heart" => Ok(Variation::Heart),
+            "disc" => Ok(Variation::Disc),
+            "spiral" => Ok(Variation::Spiral),
+            _ => Err(format!(
+                "unknown variation {:?}, expected linear, sinusoidal, spherical, swirl, horseshoe, polar, handkerchief, heart, disc or spiral",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlameTransform {
+    pub affine: Affine,
+    pub weight: f64,
+    // position on the gradient that points move towards when this transform is picked
+    pub color: f64,
+    // summed up with their amounts
+    pub variations: Vec<(Variation, f64)>,
+}
+
+fn transform(coefficients: [f64; 6], weight: f64, color: f64, variations: &[(Variation, f64)]) -> FlameTransform {
+    FlameTransform { affine: Affine::from(coefficients), weight, color, variations: variations.to_vec() }
+}
+
+impl FlameTransform {
+    fn apply(&self, p: Point) -> Point {
+        let q = self.affine.apply(p);
+        self.variations.iter().fold((0.0, 0.0), |(x, y), &(v, amount)| {
+            let (vx, vy) = v.apply(q);
+            (x + amount * vx, y + amount * vy)
+        })
+    }
+}
+
+// hit counts and summed colors of every pixel
+pub struct Histogram {
+    pub width: usize,
+    pub height: usize,
+    hits: Vec<u32>,
+    colors: Vec<[f64; 3]>,
+}
+
+impl Histogram {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, hits: vec![0; width * height], colors: vec![[0.0; 3]; width * height] }
+    }
+
+    pub fn add(&mut self, x: usize, y: usize, color: (u8, u8, u8)) {
+        let i = y * self.width + x;
+        self.hits[i] = self.hits[i].saturating_add(1);
+        let c = &mut self.colors[i];
+        c[0] += color.0 as f64;
+        c[1] += color.1 as f64;
+        c[2] += color.2 as f64;
+    }
+
+    pub fn hits(&self, x: usize, y: usize) -> u32 {
+        self.hits[y * self.width + x]
+    }
+
+    // brightness grows with the logarithm of the density, so that sparse
+    // regions stay visible next to dense ones
+    pub fn tone_map(&self, gamma: f64, brightness: f64) -> Screen {
+        let max = self.hits.iter().copied().max().unwrap_or(0).max(1) as f64;
+        let mut screen = Screen::new(self.width, self.height);
+        for (i, (&hits, c)) in self.hits.iter().zip(&self.colors).enumerate() {
+            if hits == 0 {
+                continue;
+            }
+            let alpha = ((1.0 + hits as f64).ln() / (1.0 + max).ln() * brightness).min(1.0).powf(1.0 / gamma);
+            let channel = |sum: f64| (sum / hits as f64 * alpha).round().clamp(0.0, 255.0) as u8;
+            screen.colors[i] = (channel(c[0]), channel(c[1]), channel(c[2]));
+        }
+        screen
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flame {
+    pub transforms: Vec<FlameTransform>,
+    pub gradient: Gradient,
+    pub gamma: f64,
+    pub brightness: f64,
+}
+
+// samples that decide which part of the plane is drawn
+const FIT_SAMPLES: usize = 10000;
+
+impl Flame {
+    pub fn new(transforms: Vec<FlameTransform>, gradient: Gradient) -> Self {
+        assert!(transforms.iter().any(|t| t.weight > 0.0), "flame without positive weights");
+        Self { transforms, gradient, gamma: 2.2, brightness: 1.0 }
+    }
+
+    pub fn sierpinski() -> Self {
+        let v = [(Variation::Linear, 0.6), (Variation::Sinusoidal, 0.4)];
+        Self::new(
+            vec![
+                transform([0.5, 0.0, 0.0, 0.5, -0.5, -0.5], 1.0, 0.0, &v),
+                transform([0.5, 0.0, 0.0, 0.5, 0.5, -0.5], 1.0, 0.5, &v),
+                transform([0.5, 0.0, 0.0, 0.5, 0.0, 0.5], 1.0, 1.0, &v),
+            ],
+            Gradient::fire(),
+        )
+    }
+
+    pub fn swirl() -> Self {
+        Self::new(
+            vec![
+                transform([0.8, 0.3, -0.3, 0.8, 0.0, 0.0], 1.0, 0.0, &[(Variation::Swirl, 0.8), (Variation::Linear, 0.2)]),
+                transform([0.4, 0.0, 0.0, 0.4, 1.0, 0.0], 0.5, 1.0, &[(Variation::Linear, 1.0)]),
+            ],
+            Gradient::ocean(),
+        )
+    }
+
+    // a sierpinski triangle turned inside out around the origin
+    pub fn spherical() -> Self {
+        let v = [(Variation::Linear, 1.0)];
+        Self::new(
+            vec![
+                transform([0.6, 0.0, 0.0, 0.6, 0.0, 0.0], 1.0, 0.0, &[(Variation::Spherical, 1.0)]),
+                transform([0.5, 0.0, 0.0, 0.5, 0.8, 0.0], 1.0, 0.5, &v),
+                transform([0.5, 0.0, 0.0, 0.5, -0.4, 0.7], 1.0, 1.0, &v),
+                transform([0.5, 0.0, 0.0, 0.5, -0.4, -0.7], 1.0, 0.25, &v),
+            ],
+            Gradient::ultra(),
+        )
+    }
+
+    // three transforms with random coefficients and variations
+    pub fn random(seed: u64) -> Self {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let transforms = (0..3)
+            .map(|i| {
+                let mut coefficients = [0.0; 6];
+                for c in &mut coefficients {
+                    *c = rng.f64() * 2.0 - 1.0;
+                }
+                let variations: Vec<(Variation, f64)> = (0..rng.usize(1..=2))
+                    .map(|_| (VARIATIONS[rng.usize(..VARIATIONS.len())], 0.5 + rng.f64() * 0.5))
+                    .collect();
+                transform(coefficients, 0.2 + rng.f64(), i as f64 / 2.0, &variations[..])
+            })
+            .collect();
+        Self::new(transforms, Gradient::rainbow())
+    }
+
+    // every transform only uses this variation
+    pub fn with_variation(mut self, variation: Variation) -> Self {
+        for t in &mut self.transforms {
+            t.variations = vec![(variation, 1.0)];
+        }
+        self
+    }
+
+    // the chaos game, with the position on the gradient of every point
+    fn iterate<F: FnMut(Point, f64)>(&self, n: usize, mut plot: F) {
+        let random_point = || (fastrand::f64() * 2.0 - 1.0, fastrand::f64() * 2.0 - 1.0);
+        let mut p = random_point();
+        let mut color = fastrand::f64();
+        let mut warmup = WARMUP;
+        for _ in 0..n + WARMUP {
+            let t = pick(&self.transforms[..], |t| t.weight);
+            p = t.apply(p);
+            color = (color + t.color) / 2.0;
+            // variations like spherical can throw points to infinity, the
+            // new point has to find its way to the attractor again
+            if !(p.0.is_finite() && p.1.is_finite()) {
+                p = random_point();
+                warmup = WARMUP;
+                continue;
+            }
+            if warmup > 0 {
+                warmup -= 1;
+            } else {
+                plot(p, color);
+            }
+        }
+    }
+
+    // the part of the plane where most points land, a few outliers
+    // would otherwise shrink the flame to a dot
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (mut xs, mut ys) = (Vec::with_capacity(FIT_SAMPLES), Vec::with_capacity(FIT_SAMPLES));
+        self.iterate(FIT_SAMPLES, |(x, y), _| {
+            xs.push(x);
+            ys.push(y);
+        });
+        xs.sort_by(f64::total_cmp);
+        ys.sort_by(f64::total_cmp);
+        let quantile = |v: &[f64], q: f64| v.get((q * (v.len().saturating_sub(1)) as f64) as usize).copied().unwrap_or(0.0);
+        (quantile(&xs, 0.01), quantile(&xs, 0.99), quantile(&ys, 0.01), quantile(&ys, 0.99))
+    }
+
+    // n points into a w x h histogram, fitted without distortion, y grows upwards
+    pub fn histogram(&self, n: usize, w: usize, h: usize) -> Histogram {
+        let mut histogram = Histogram::new(w, h);
+        if w == 0 || h == 0 {
+            return histogram;
+        }
+        let (xmin, xmax, ymin, ymax) = self.bounds();
+        let (bw, bh) = ((xmax - xmin).max(f64::EPSILON), (ymax - ymin).max(f64::EPSILON));
+        // a small margin around the dense part
+        let scale = (w as f64 / bw).min(h as f64 / bh) * 0.9;
+        let x0 = w as f64 / 2.0 - (xmin + bw / 2.0) * scale;
+        let y0 = h as f64 / 2.0 + (ymin + bh / 2.0) * scale;
+        self.iterate(n, |(x, y), color| {
+            let (px, py) = (x0 + x * scale, y0 - y * scale);
+            if px >= 0.0 && py >= 0.0 && px < w as f64 && py < h as f64 {
+                histogram.add(px as usize, py as usize, self.gradient.at(color));
+            }
+        });
+        histogram
+    }
+
+    pub fn render(&self, n: usize, w: usize, h: usize) -> Screen {
+        self.histogram(n, w, h).tone_map(self.gamma, self.brightness)
+    }
+}
+
+// sierpinski, swirl, spherical or random[:SEED]
+impl std::str::FromStr for Flame {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "sierpinski" => Ok(Self::sierpinski()),
+            None if s == "swirl" => Ok(Self::swirl()),
+            None if s == "spherical" => Ok(Self::spherical()),
+            None if s == "random" => Ok(Self::random(fastrand::u64(..))),
+            Some(("random", seed)) => seed
+                .parse()
+                .map(Self::random)
+                .map_err(|_| format!("invalid seed {:?} in flame {:?}", seed, s)),
+            _ => Err(format!("unknown flame {:?}, expected sierpinski, swirl, spherical or random[:SEED]", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Point, b: Point) {
+        assert!((a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn variations_at_known_points() {
+        let (sin1, cos1) = (1f64.sin(), 1f64.cos());
+        assert_close(Variation::Linear.apply((0.3, -0.4)), (0.3, -0.4));
+        assert_close(Variation::Sinusoidal.apply((PI / 2.0, 0.0)), (1.0, 0.0));
+        assert_close(Variation::Spherical.apply((2.0, 0.0)), (0.5, 0.0));
+        assert_close(Variation::Swirl.apply((1.0, 0.0)), (sin1, cos1));
+        assert_close(Variation::Horseshoe.apply((1.0, 0.0)), (1.0, 0.0));
+        assert_close(Variation::Horseshoe.apply((0.0, 1.0)), (-1.0, 0.0));
+        // theta is 0 on the y axis and pi / 2 on the x axis
+        assert_close(Variation::Polar.apply((0.0, 1.0)), (0.0, 0.0));
+        assert_close(Variation::Polar.apply((2.0, 0.0)), (0.5, 1.0));
+        assert_close(Variation::Handkerchief.apply((0.0, 1.0)), (sin1, cos1));
+        assert_close(Variation::Heart.apply((0.0, 1.0)), (0.0, -1.0));
+        assert_close(Variation::Disc.apply((1.0, 0.0)), (0.0, -0.5));
+        assert_close(Variation::Spiral.apply((0.0, 1.0)), (1.0 + sin1, -cos1));
+        // no division by zero at the origin
+        for v in VARIATIONS {
+            let (x, y) = v.apply((0.0, 0.0));
+            assert!(x.is_finite() && y.is_finite(), "{:?}", v);
+        }
+    }
+
+    #[test]
+    fn transforms_sum_their_variations() {
+        let t = transform([2.0, 0.0, 0.0, 2.0, 0.0, 0.0], 1.0, 0.0, &[(Variation::Linear, 0.5), (Variation::Spherical, 2.0)]);
+        // (1, 0) -> (2, 0) -> 0.5 (2, 0) + 2 (0.5, 0)
+        assert_close(t.apply((1.0, 0.0)), (2.0, 0.0));
+    }
+
+    #[test]
+    fn tone_map_grows_with_the_log_density() {
+        let mut histogram = Histogram::new(4, 1);
+        for (x, hits) in [(1, 1), (2, 10), (3, 100)] {
+            for _ in 0..hits {
+                histogram.add(x, 0, (255, 128, 0));
+            }
+        }
+        let screen = histogram.tone_map(1.0, 1.0);
+        assert_eq!(screen.colors[0], (0, 0, 0));
+        // the densest pixel gets its full color
+        assert_eq!(screen.colors[3], (255, 128, 0));
+        assert!(screen.colors[1].0 < screen.colors[2].0 && screen.colors[2].0 < screen.colors[3].0);
+        // ln 2 / ln 101 of the way
+        assert_eq!(screen.colors[1].0, (255.0 * 2f64.ln() / 101f64.ln()).round() as u8);
+        // the hue stays, only the brightness changes
+        assert_eq!(screen.colors[2].2, 0);
+        // gamma brightens sparse pixels, brightness saturates
+        assert!(histogram.tone_map(2.2, 1.0).colors[1].0 > screen.colors[1].0);
+        assert_eq!(histogram.tone_map(1.0, 100.0).colors[1], (255, 128, 0));
+    }
+
+    #[test]
+    fn histogram_fills_the_target_without_distortion() {
+        fastrand::seed(5);
+        let (w, h) = (60, 30);
+        let histogram = Flame::sierpinski().histogram(50000, w, h);
+        let hit: Vec<(usize, usize)> = (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .filter(|&(x, y)| histogram.hits(x, y) > 0)
+            .collect();
+        let (xmin, xmax) = (hit.iter().map(|p| p.0).min().unwrap(), hit.iter().map(|p| p.0).max().unwrap());
+        let (ymin, ymax) = (hit.iter().map(|p| p.1).min().unwrap(), hit.iter().map(|p| p.1).max().unwrap());
+        // the flame is about as wide as high, so the height limits it and it is centered
+        assert!(ymax - ymin + 1 >= h * 8 / 10, "{}..{}", ymin, ymax);
+        assert!((xmin + xmax).abs_diff(w - 1) <= 4, "{}..{}", xmin, xmax);
+        assert!(((xmax - xmin) as f64 / (ymax - ymin) as f64 - 1.0).abs() < 0.3);
+        assert!(Flame::sierpinski().histogram(100, 0, 10).hits.is_empty());
+    }
+
+    #[test]
+    fn escaped_points_warm_up_again() {
+        // every point overflows after a few steps, long before it could settle
+        let flame = Flame::new(vec![transform([1e100, 0.0, 0.0, 1e100, 0.0, 0.0], 1.0, 0.0, &[(Variation::Linear, 1.0)])], Gradient::fire());
+        let mut plotted = 0;
+        flame.iterate(1000, |_, _| plotted += 1);
+        assert_eq!(plotted, 0);
+
+        let mut plotted = 0;
+        Flame::sierpinski().iterate(1000, |_, _| plotted += 1);
+        assert_eq!(plotted, 1000);
+    }
+
+    #[test]
+    fn parses_flames() {
+        assert_eq!("sierpinski".parse::<Flame>().unwrap(), Flame::sierpinski());
+        assert_eq!("random:42".parse::<Flame>().unwrap(), Flame::random(42));
+        assert!("random".parse::<Flame>().is_ok());
+        for s in ["", "fern", "random:x", "random:-1", "sierpinski:1", "swirl:"] {
+            assert!(s.parse::<Flame>().is_err(), "{:?}", s);
+        }
+        assert_eq!("disc".parse::<Variation>(), Ok(Variation::Disc));
+        assert!("Disc".parse::<Variation>().is_err());
+    }
+}
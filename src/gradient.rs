@@ -0,0 +1,121 @@
+use crate::primitive::parse_color;
+
+// colors at positions between 0 and 1, linearly interpolated in between
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f64, (u8, u8, u8))>,
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<(f64, (u8, u8, u8))>) -> Self {
+        assert!(!stops.is_empty(), "gradient without stops");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops }
+    }
+
+    pub fn even(colors: &[(u8, u8, u8)]) -> Self {
+        let n = colors.len().saturating_sub(1).max(1) as f64;
+        Self::new(colors.iter().enumerate().map(|(i, &c)| (i as f64 / n, c)).collect())
+    }
+
+    pub fn gray() -> Self {
+        Self::even(&[(0, 0, 0), (255, 255, 255)])
+    }
+
+    pub fn fire() -> Self {
+        Self::even(&[(0, 0, 0), (128, 0, 0), (255, 64, 0), (255, 200, 0), (255, 255, 255)])
+    }
+
+    pub fn ocean() -> Self {
+        Self::even(&[(0, 0, 32), (0, 48, 128), (0, 160, 200), (220, 255, 255)])
+    }
+
+    // the well known default palette of ultra fractal
+    pub fn ultra() -> Self {
+        Self::new(vec![
+            (0.0, (0, 7, 100)),
+            (0.16, (32, 107, 203)),
+            (0.42, (237, 255, 255)),
+            (0.6425, (255, 170, 0)),
+            (0.8575, (0, 2, 0)),
+            (1.0, (0, 7, 100)),
+        ])
+    }
+
+    pub fn rainbow() -> Self {
+        Self::even(&[(255, 0, 0), (255, 255, 0), (0, 255, 0), (0, 255, 255), (0, 0, 255), (255, 0, 255)])
+    }
+
+    pub fn stops(&self) -> &[(f64, (u8, u8, u8))] {
+        &self.stops[..]
+    }
+
+    // t is clamped to the first and last stop
+    pub fn at(&self, t: f64) -> (u8, u8, u8) {
+        let i = self.stops.partition_point(|s| s.0 <= t);
+        if i == 0 {
+            return self.stops[0].1;
+        }
+        if i == self.stops.len() {
+            return self.stops[i - 1].1;
+        }
+        let (t0, a) = self.stops[i - 1];
+        let (t1, b) = self.stops[i];
+        let f = (t - t0) / (t1 - t0);
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * f).round() as u8;
+        (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+    }
+}
+
+// a name, evenly spaced RRGGBB,RRGGBB,... or RRGGBB@POS,RRGGBB@POS,...
+impl std::str::FromStr for Gradient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gray" => return Ok(Self::gray()),
+            "fire" => return Ok(Self::fire()),
+            "ocean" => return Ok(Self::ocean()),
+            "ultra" => return Ok(Self::ultra()),
+            "rainbow" => return Ok(Self::rainbow()),
+            _ => {}
+        }
+        let invalid = || format!(
+            "unknown gradient {:?}, expected gray, fire, ocean, ultra, rainbow or RRGGBB[@POS],...",
+            s
+        );
+        let parse = |c: &str| parse_color(c.trim()).map_err(|_| invalid());
+        let parts: Vec<&str> = s.split(',').collect();
+        if parts.iter().all(|p| p.contains('@')) {
+            let stops = parts
+                .iter()
+                .map(|p| {
+                    let (color, pos) = p.split_once('@').unwrap();
+                    let pos: f64 = pos.trim().parse().map_err(|_| invalid())?;
+                    Ok((pos, parse(color)?))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(Self::new(stops))
+        } else {
+            let colors = parts.iter().map(|p| parse(p)).collect::<Result<Vec<_>, _>>()?;
+            Ok(Self::even(&colors[..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors_and_stops() {
+        let even: Gradient = "000000, #ff0000,ffffff".parse().unwrap();
+        assert_eq!(even.stops(), [(0.0, (0, 0, 0)), (0.5, (255, 0, 0)), (1.0, (255, 255, 255))]);
+        let stops: Gradient = "ffffff@1,000000@0".parse().unwrap();
+        assert_eq!(stops.stops(), [(0.0, (0, 0, 0)), (1.0, (255, 255, 255))]);
+        assert_eq!("fire".parse::<Gradient>().unwrap(), Gradient::fire());
+        for invalid in ["", "fff", "ff0000,+f0000", "ff0000@x,000000@1", "nope"] {
+            assert!(invalid.parse::<Gradient>().is_err(), "{:?}", invalid);
+        }
+    }
+}
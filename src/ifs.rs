@@ -0,0 +1,241 @@
+use crate::primitive::{parse_color, Pixel, Rect};
+
+pub type Point = (f64, f64);
+
+// (x, y) -> (a x + b y + e, c x + d y + f)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Affine {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+}
+
+impl Affine {
+    pub fn new(a: f64, b: f64, c: f64, d: f64, e: f64, f: f64) -> Self {
+        Self { a, b, c, d, e, f }
+    }
+
+    pub fn apply(&self, (x, y): Point) -> Point {
+        (self.a * x + self.b * y + self.e, self.c * x + self.d * y + self.f)
+    }
+}
+
+impl From<[f64; 6]> for Affine {
+    fn from([a, b, c, d, e, f]: [f64; 6]) -> Self {
+        Self::new(a, b, c, d, e, f)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub affine: Affine,
+    // how often the transform is picked, relative to the others
+    pub weight: f64,
+    // points take the color of the last transform that moved them
+    pub color: (u8, u8, u8),
+}
+
+fn transform(coefficients: [f64; 6], weight: f64, color: (u8, u8, u8)) -> Transform {
+    Transform { affine: Affine::from(coefficients), weight, color }
+}
+
+// the first points of a chaos game haven't reached the attractor yet
+pub const WARMUP: usize = 20;
+
+// a random item, each as likely as its weight, negative weights count as zero.
+// there has to be one with a positive weight
+pub fn pick<T>(items: &[T], weight: impl Fn(&T) -> f64) -> &T {
+    let total: f64 = items.iter().map(|t| weight(t).max(0.0)).sum();
+    let mut r = fastrand::f64() * total;
+    for t in items {
+        r -= weight(t).max(0.0);
+        if r < 0.0 {
+            return t;
+        }
+    }
+    // r can stay just above zero through rounding
+    items.iter().rev().find(|t| weight(t) > 0.0).unwrap()
+}
+
+// an iterated function system, drawn with the chaos game
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ifs {
+    transforms: Vec<Transform>,
+}
+
+impl Ifs {
+    pub fn new(transforms: Vec<Transform>) -> Self {
+        assert!(!transforms.is_empty(), "ifs without transforms");
+        assert!(transforms.iter().any(|t| t.weight > 0.0), "ifs without positive weights");
+        Self { transforms }
+    }
+
+    pub fn fern() -> Self {
+        Self::new(vec![
+            transform([0.0, 0.0, 0.0, 0.16, 0.0, 0.0], 0.01, (120, 90, 30)),
+            transform([0.85, 0.04, -0.04, 0.85, 0.0, 1.6], 0.85, (200, 200, 40)),
+            transform([0.2, -0.26, 0.23, 0.22, 0.0, 1.6], 0.07, (120, 200, 40)),
+            transform([-0.15, 0.28, 0.26, 0.24, 0.0, 0.44], 0.07, (60, 160, 40)),
+        ])
+    }
+
+    pub fn sierpinski() -> Self {
+        let h = 3f64.sqrt() / 4.0;
+        Self::new(vec![
+            transform([0.5, 0.0, 0.0, 0.5, 0.0, 0.0], 1.0, (255, 60, 60)),
+            transform([0.5, 0.0, 0.0, 0.5, 0.5, 0.0], 1.0, (60, 255, 60)),
+            transform([0.5, 0.0, 0.0, 0.5, 0.25, h], 1.0, (60, 60, 255)),
+        ])
+    }
+
+    // heighway dragon
+    pub fn dragon() -> Self {
+        Self::new(vec![
+            transform([0.5, -0.5, 0.5, 0.5, 0.0, 0.0], 1.0, (255, 120, 0)),
+            transform([-0.5, -0.5, 0.5, -0.5, 1.0, 0.0], 1.0, (255, 220, 0)),
+        ])
+    }
+
+    pub fn maple() -> Self {
+        Self::new(vec![
+            transform([0.14, 0.01, 0.0, 0.51, -0.08, -1.31], 0.10, (140, 40, 20)),
+            transform([0.43, 0.52, -0.45, 0.50, 1.49, -0.75], 0.35, (230, 80, 20)),
+            transform([0.45, -0.49, 0.47, 0.47, -1.62, -0.74], 0.35, (230, 140, 20)),
+            transform([0.49, 0.0, 0.0, 0.51, 0.02, 1.62], 0.20, (200, 40, 20)),
+        ])
+    }
+
+    pub fn transforms(&self) -> &[Transform] {
+        &self.transforms[..]
+    }
+
+    // n points of the attractor with their colors
+    pub fn points(&self, n: usize) -> Vec<(Point, (u8, u8, u8))> {
+        let mut p = (0.0, 0.0);
+        let mut points = Vec::with_capacity(n);
+        for i in 0..n + WARMUP {
+            let t = pick(&self.transforms[..], |t| t.weight);
+            p = t.affine.apply(p);
+            if i >= WARMUP {
+                points.push((p, t.color));
+            }
+        }
+        points
+    }
+
+    // n points scaled to fit into rect without distortion, y grows upwards,
+    // every pixel is only sent once with the last color it got
+    pub fn render(&self, n: usize, rect: Rect) -> Vec<Pixel> {
+        let points = self.points(n);
+        if points.is_empty() || rect.w == 0 || rect.h == 0 {
+            return Vec::new();
+        }
+        let (mut xmin, mut xmax, mut ymin, mut ymax) = (f64::MAX, f64::MIN, f64::MAX, f64::MIN);
+        for &((x, y), _) in &points {
+            xmin = xmin.min(x);
+            xmax = xmax.max(x);
+            ymin = ymin.min(y);
+            ymax = ymax.max(y);
+        }
+        let (w, h) = ((xmax - xmin).max(f64::EPSILON), (ymax - ymin).max(f64::EPSILON));
+        let scale = ((rect.w - 1) as f64 / w).min((rect.h - 1) as f64 / h);
+        // centered in the leftover space
+        let x0 = (rect.w - 1) as f64 / 2.0 - (xmin + w / 2.0) * scale;
+        let y0 = (rect.h - 1) as f64 / 2.0 + (ymin + h / 2.0) * scale;
+
+        let mut grid: Vec<Option<(u8, u8, u8)>> = vec![None; rect.w * rect.h];
+        for ((x, y), color) in points {
+            let px = (x0 + x * scale).round().clamp(0.0, (rect.w - 1) as f64) as usize;
+            let py = (y0 - y * scale).round().clamp(0.0, (rect.h - 1) as f64) as usize;
+            grid[py * rect.w + px] = Some(color);
+        }
+        grid.into_iter()
+            .enumerate()
+            .filter_map(|(i, color)| color.map(|color| Pixel { x: rect.x + i % rect.w, y: rect.y + i / rect.w, color }))
+            .collect()
+    }
+}
+
+// fern, sierpinski, dragon, maple or the transforms themselves, separated by
+// semicolons: A,B,C,D,E,F,WEIGHT[,RRGGBB]
+impl std::str::FromStr for Ifs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fern" => return Ok(Self::fern()),
+            "sierpinski" => return Ok(Self::sierpinski()),
+            "dragon" => return Ok(Self::dragon()),
+            "maple" => return Ok(Self::maple()),
+            _ => {}
+        }
+        let invalid = || format!(
+            "unknown ifs {:?}, expected fern, sierpinski, dragon, maple or A,B,C,D,E,F,WEIGHT[,RRGGBB];...",
+            s
+        );
+        let mut transforms = Vec::new();
+        for spec in s.split(';') {
+            let parts: Vec<&str> = spec.split(',').map(str::trim).collect();
+            if parts.len() != 7 && parts.len() != 8 {
+                return Err(invalid());
+            }
+            let numbers = parts[..7]
+                .iter()
+                .map(|p| p.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid())?;
+            let color = match parts.get(7) {
+                Some(c) => parse_color(c).map_err(|_| invalid())?,
+                None => (255, 255, 255),
+            };
+            if numbers[..6].iter().any(|n| !n.is_finite()) || !(numbers[6].is_finite() && numbers[6] >= 0.0) {
+                return Err(invalid());
+            }
+            let coefficients = [numbers[0], numbers[1], numbers[2], numbers[3], numbers[4], numbers[5]];
+            transforms.push(transform(coefficients, numbers[6], color));
+        }
+        if !transforms.iter().any(|t| t.weight > 0.0) {
+            return Err(format!("ifs {:?} needs a transform with positive weight", s));
+        }
+        Ok(Self::new(transforms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_follows_the_weights() {
+        fastrand::seed(1);
+        let weights = [0.0, 1.0, -2.0, 3.0];
+        let mut counts = [0; 4];
+        for _ in 0..40000 {
+            let w = pick(&weights[..], |&w| w);
+            counts[weights.iter().position(|x| x == w).unwrap()] += 1;
+        }
+        assert_eq!((counts[0], counts[2]), (0, 0));
+        assert!((9000..11000).contains(&counts[1]), "{:?}", counts);
+    }
+
+    #[test]
+    fn parses_presets_and_transforms() {
+        assert_eq!("fern".parse::<Ifs>().unwrap(), Ifs::fern());
+        let ifs: Ifs = "0.5,0,0,0.5,0,0,1;0.5,0,0,0.5,0.5,0,2,ff8000".parse().unwrap();
+        assert_eq!(ifs.transforms().len(), 2);
+        assert_eq!(ifs.transforms()[1].color, (255, 128, 0));
+        assert_eq!(ifs.transforms()[1].affine.apply((1.0, 1.0)), (1.0, 0.5));
+        assert!("0.5,0,0,0.5,0,0,0".parse::<Ifs>().is_err());
+        assert!("0.5,0,0,0.5,0,0".parse::<Ifs>().is_err());
+        assert!("0.5,0,0,0.5,0,0,1,ff80".parse::<Ifs>().is_err());
+        assert!("0.5,0,0,0.5,0,0,1,+f8000".parse::<Ifs>().is_err());
+        assert!("0.5,0,0,0.5,0,0,NaN".parse::<Ifs>().is_err());
+        assert!("0.5,0,0,0.5,0,0,inf".parse::<Ifs>().is_err());
+        assert!("0.5,0,0,0.5,0,0,-1;0.5,0,0,0.5,0,0,1".parse::<Ifs>().is_err());
+        assert!("NaN,0,0,0.5,0,0,1".parse::<Ifs>().is_err());
+        assert!("0.5,0,0,0.5,-inf,0,1".parse::<Ifs>().is_err());
+    }
+}
@@ -0,0 +1,500 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::client::PixelflutClient;
+use crate::error::{PixelflutError, Result};
+use crate::primitive::Rect;
+use crate::protocol::Protocol;
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub colors: Vec<(u8, u8, u8)>,
+    // None if every pixel is opaque
+    pub alpha: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Filter::Nearest),
+            "bilinear" => Ok(Filter::Bilinear),
+            _ => Err(format!("unknown filter {:?}, expected nearest or bilinear", s)),
+        }
+    }
+}
+
+fn decode_error(s: &str) -> PixelflutError {
+    PixelflutError::Decode(s.to_string())
+}
+
+fn opaque_or(alpha: Vec<u8>) -> Option<Vec<u8>> {
+    if alpha.iter().all(|&a| a == 255) {
+        None
+    } else {
+        Some(alpha)
+    }
+}
+
+// skips whitespace and comments, then reads one decimal number of a pnm header
+fn ppm_number(data: &[u8], pos: &mut usize) -> Result<usize> {
+    loop {
+        match data.get(*pos) {
+            Some(b'#') => {
+                while data.get(*pos).is_some_and(|&b| b != b'\n') {
+                    *pos += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+    let start = *pos;
+    while data.get(*pos).is_some_and(u8::is_ascii_digit) {
+        *pos += 1;
+    }
+    std::str::from_utf8(&data[start..*pos])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| decode_error("malformed ppm header"))
+}
+
+// P3 (ascii) and P6 (binary), with 8 or 16 bits per sample
+fn decode_ppm(data: &[u8]) -> Result<Image> {
+    let mut pos = 2;
+    let width = ppm_number(data, &mut pos)?;
+    let height = ppm_number(data, &mut pos)?;
+    let maxval = ppm_number(data, &mut pos)?;
+    if width == 0 || height == 0 || maxval == 0 || maxval > 0xffff {
+        return Err(decode_error("malformed ppm header"));
+    }
+    // three samples per pixel
+    let len = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(3))
+        .ok_or_else(|| decode_error("ppm too large"))?;
+    let scale = |v: usize| (v.min(maxval) * 255 / maxval) as u8;
+    let mut samples = Vec::with_capacity(len.min(data.len()));
+    if data[1] == b'6' {
+        // exactly one whitespace byte separates the header from the samples
+        pos += 1;
+        let sample_size = if maxval < 256 { 1 } else { 2 };
+        let body = len
+            .checked_mul(sample_size)
+            .and_then(|len| data.get(pos..pos.checked_add(len)?))
+            .ok_or_else(|| decode_error("truncated ppm"))?;
+        for sample in body.chunks_exact(sample_size) {
+            let v = sample.iter().fold(0usize, |v, &b| (v << 8) | b as usize);
+            samples.push(scale(v));
+        }
+    } else {
+        for _ in 0..len {
+            samples.push(scale(ppm_number(data, &mut pos)?));
+        }
+    }
+    let colors = samples.chunks_exact(3).map(|c| (c[0], c[1], c[2])).collect();
+    Ok(Image { width, height, colors, alpha: None })
+}
+
+fn le16(data: &[u8], pos: usize) -> u32 {
+    data[pos] as u32 | ((data[pos + 1] as u32) << 8)
+}
+
+fn le32(data: &[u8], pos: usize) -> u32 {
+    le16(data, pos) | (le16(data, pos + 2) << 16)
+}
+
+// uncompressed windows bitmaps with 1, 4, 8, 24 or 32 bits per pixel
+fn decode_bmp(data: &[u8]) -> Result<Image> {
+    if data.len() < 54 {
+        return Err(decode_error("truncated bmp"));
+    }
+    let offset = le32(data, 10) as usize;
+    let header_size = le32(data, 14) as usize;
+    let width = le32(data, 18) as i32;
+    let height = le32(data, 22) as i32;
+    let bpp = le16(data, 28) as usize;
+    let compression = le32(data, 30);
+    if header_size < 40 {
+        return Err(decode_error("unsupported bmp header"));
+    }
+    // 3 is BI_BITFIELDS, which we only accept with the usual BGRA layout
+    if compression != 0 && !(compression == 3 && bpp == 32) {
+        return Err(decode_error("compressed bmp"));
+    }
+    if !matches!(bpp, 1 | 4 | 8 | 24 | 32) {
+        return Err(decode_error("unsupported bits per pixel in bmp"));
+    }
+    if width <= 0 || height == 0 {
+        return Err(decode_error("malformed bmp header"));
+    }
+    // a negative height means the rows are stored top down
+    let top_down = height < 0;
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+    let palette: Vec<(u8, u8, u8)> = if bpp <= 8 {
+        let colors_used = match le32(data, 46) as usize {
+            0 => 1 << bpp,
+            n => n.min(1 << bpp),
+        };
+        let start = 14 + header_size;
+        data.get(start..start + colors_used * 4)
+            .ok_or_else(|| decode_error("truncated bmp palette"))?
+            .chunks_exact(4)
+            .map(|c| (c[2], c[1], c[0]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // rows are padded to 4 bytes
+    let row_size = (width * bpp).div_ceil(32) * 4;
+    let body = row_size
+        .checked_mul(height)
+        .and_then(|len| data.get(offset..offset.checked_add(len)?))
+        .ok_or_else(|| decode_error("truncated bmp"))?;
+    let mut colors = Vec::with_capacity(width * height);
+    let mut alpha = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row_index = if top_down { y } else { height - 1 - y };
+        let row = &body[row_index * row_size..(row_index + 1) * row_size];
+        for x in 0..width {
+            let (color, a) = match bpp {
+                24 => ((row[x * 3 + 2], row[x * 3 + 1], row[x * 3]), 255),
+                32 => ((row[x * 4 + 2], row[x * 4 + 1], row[x * 4]), row[x * 4 + 3]),
+                _ => {
+                    let bit = x * bpp;
+                    let index = (row[bit / 8] >> (8 - bpp - bit % 8)) & ((1 << bpp) - 1) as u8;
+                    let color = *palette
+                        .get(index as usize)
+                        .ok_or_else(|| decode_error("bmp palette index out of range"))?;
+                    (color, 255)
+                }
+            };
+            colors.push(color);
+            alpha.push(a);
+        }
+    }
+    // most 32-bit bitmaps leave the fourth byte at zero instead of storing alpha
+    let alpha = if alpha.iter().all(|&a| a == 0) { None } else { opaque_or(alpha) };
+    Ok(Image { width, height, colors, alpha })
+}
+
+fn decode_png(data: &[u8]) -> Result<Image> {
+    let png_error = |e: png::DecodingError| PixelflutError::Decode(e.to_string());
+    let mut decoder = png::Decoder::new(data);
+    // palette, low bit depths and 16 bit samples all end up as 8 bit gray or rgb
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).map_err(png_error)?;
+    let buf = &buf[..frame.buffer_size()];
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let (colors, alpha): (Vec<(u8, u8, u8)>, Vec<u8>) = match frame.color_type {
+        png::ColorType::Grayscale => buf.iter().map(|&g| ((g, g, g), 255)).unzip(),
+        png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).map(|c| ((c[0], c[0], c[0]), c[1])).unzip(),
+        png::ColorType::Rgb => buf.chunks_exact(3).map(|c| ((c[0], c[1], c[2]), 255)).unzip(),
+        png::ColorType::Rgba => buf.chunks_exact(4).map(|c| ((c[0], c[1], c[2]), c[3])).unzip(),
+        png::ColorType::Indexed => return Err(decode_error("indexed png was not expanded")),
+    };
+    Ok(Image { width, height, colors, alpha: opaque_or(alpha) })
+}
+
+impl Image {
+    // the format is guessed from the first bytes, not the file name
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        match data {
+            [0x89, b'P', b'N', b'G', ..] => decode_png(data),
+            [b'B', b'M', ..] => decode_bmp(data),
+            [b'P', b'3' | b'6', ..] => decode_ppm(data),
+            _ => Err(decode_error("unknown image format, expected png, bmp or ppm")),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::decode(&std::fs::read(path)?[..])
+    }
+
+    fn alpha_at(&self, i: usize) -> u8 {
+        self.alpha.as_ref().map_or(255, |alpha| alpha[i])
+    }
+
+    fn sample_nearest(&self, fx: f64, fy: f64) -> ((u8, u8, u8), u8) {
+        let x = (fx as usize).min(self.width - 1);
+        let y = (fy as usize).min(self.height - 1);
+        let i = y * self.width + x;
+        (self.colors[i], self.alpha_at(i))
+    }
+
+    // colors are weighted by alpha, so transparent pixels don't bleed into the edges
+    fn sample_bilinear(&self, fx: f64, fy: f64) -> ((u8, u8, u8), u8) {
+        let fx = (fx - 0.5).clamp(0.0, (self.width - 1) as f64);
+        let fy = (fy - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let (x0, y0) = (fx as usize, fy as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+        let mut sum = (0.0, 0.0, 0.0);
+        let mut alpha_sum = 0.0;
+        for (x, y, weight) in [(x0, y0, (1.0 - tx) * (1.0 - ty)), (x1, y0, tx * (1.0 - ty)), (x0, y1, (1.0 - tx) * ty), (x1, y1, tx * ty)] {
+            let i = y * self.width + x;
+            let a = weight * self.alpha_at(i) as f64;
+            let c = self.colors[i];
+            sum.0 += c.0 as f64 * a;
+            sum.1 += c.1 as f64 * a;
+            sum.2 += c.2 as f64 * a;
+            alpha_sum += a;
+        }
+        if alpha_sum <= 0.0 {
+            return ((0, 0, 0), 0);
+        }
+        let color = (
+            (sum.0 / alpha_sum).round() as u8,
+            (sum.1 / alpha_sum).round() as u8,
+            (sum.2 / alpha_sum).round() as u8,
+        );
+        (color, alpha_sum.round() as u8)
+    }
+
+    pub fn scale(&self, width: usize, height: usize, filter: Filter) -> Image {
+        if width == self.width && height == self.height {
+            return self.clone();
+        }
+        let mut colors = Vec::with_capacity(width * height);
+        let mut alpha = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // center of the target pixel in source coordinates
+                let fx = (x as f64 + 0.5) * self.width as f64 / width as f64;
+                let fy = (y as f64 + 0.5) * self.height as f64 / height as f64;
+                let (color, a) = match filter {
+                    Filter::Nearest => self.sample_nearest(fx, fy),
+                    Filter::Bilinear => self.sample_bilinear(fx, fy),
+                };
+                colors.push(color);
+                alpha.push(a);
+            }
+        }
+        let alpha = if self.alpha.is_some() { opaque_or(alpha) } else { None };
+        Image { width, height, colors, alpha }
+    }
+
+    // largest rectangle at the origin of bounds with the aspect ratio of the image,
+    // images that already fit keep their size
+    pub fn fit(&self, bounds: Rect) -> Rect {
+        let scale = (bounds.w as f64 / self.width as f64)
+            .min(bounds.h as f64 / self.height as f64)
+            .min(1.0);
+        Rect {
+            x: bounds.x,
+            y: bounds.y,
+            w: ((self.width as f64 * scale).round() as usize).max(1),
+            h: ((self.height as f64 * scale).round() as usize).max(1),
+        }
+    }
+
+    // composites the image over background, which has to have the same size
+    pub fn blend(&self, background: &[(u8, u8, u8)]) -> Vec<(u8, u8, u8)> {
+        let mix = |c: u8, b: u8, a: u8| ((c as u32 * a as u32 + b as u32 * (255 - a as u32) + 127) / 255) as u8;
+        self.colors
+            .iter()
+            .zip(background)
+            .enumerate()
+            .map(|(i, (c, b))| {
+                let a = self.alpha_at(i);
+                (mix(c.0, b.0, a), mix(c.1, b.1, a), mix(c.2, b.2, a))
+            })
+            .collect()
+    }
+}
+
+// scales the image to rect, blends it over what the server shows if it has transparency
+pub fn draw<S: Read + Write, P: Protocol>(client: &mut PixelflutClient<S, P>, image: &Image, rect: Rect, filter: Filter) -> Result<()> {
+    let scaled = image.scale(rect.w, rect.h, filter);
+    let colors = if scaled.alpha.is_some() {
+        let mut background = vec![(0, 0, 0); rect.w * rect.h];
+        client.rectangle_get(&mut background[..], rect)?;
+        scaled.blend(&background[..])
+    } else {
+        scaled.colors
+    };
+    client.rectangle_print(&colors[..], rect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_err(data: &[u8]) -> String {
+        match Image::decode(data) {
+            Err(PixelflutError::Decode(e)) => e,
+            result => panic!("expected a decode error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn ppm_ascii() {
+        let image = Image::decode(b"P3\n# a comment\n2 1\n255\n255 0 0  0 128 255\n").unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.colors, [(255, 0, 0), (0, 128, 255)]);
+        assert!(image.alpha.is_none());
+        // samples are scaled from maxval to 255
+        let image = Image::decode(b"P3 1 1 15 15 0 5").unwrap();
+        assert_eq!(image.colors, [(255, 0, 85)]);
+    }
+
+    #[test]
+    fn ppm_binary() {
+        let mut data = b"P6 2 1 255\n".to_vec();
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(Image::decode(&data[..]).unwrap().colors, [(1, 2, 3), (4, 5, 6)]);
+        let mut data = b"P6 1 1 65535\n".to_vec();
+        data.extend_from_slice(&[0xff, 0xff, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(Image::decode(&data[..]).unwrap().colors, [(255, 127, 0)]);
+    }
+
+    #[test]
+    fn ppm_truncated_or_hostile() {
+        assert_eq!(decode_err(b"P3 2 1 255 1 2 3 4"), "malformed ppm header");
+        assert_eq!(decode_err(b"P6 2 1 255\n\x01\x02\x03\x04\x05"), "truncated ppm");
+        assert_eq!(decode_err(b"P3 2"), "malformed ppm header");
+        assert_eq!(decode_err(b"P3 0 1 255"), "malformed ppm header");
+        assert_eq!(decode_err(b"P3 1 1 70000 1 1 1"), "malformed ppm header");
+        assert_eq!(decode_err(b"P3 1 1 255 -1 0 0"), "malformed ppm header");
+        assert_eq!(decode_err(b"P3 99999999999999999999999 1 255"), "malformed ppm header");
+        // three samples per pixel overflow, but width and height alone don't
+        assert_eq!(decode_err(b"P3 6148914691236517206 1 255 1 2 3"), "ppm too large");
+        assert_eq!(decode_err(b"P6 6148914691236517206 1 255\n\x01\x02\x03"), "ppm too large");
+        assert_eq!(decode_err(b"P6 4294967296 4294967296 255\n"), "ppm too large");
+        assert_eq!(decode_err(b"P6 65536 65536 65535\n\x01"), "truncated ppm");
+    }
+
+    // a BITMAPINFOHEADER bitmap, rows already padded
+    fn bmp(width: i32, height: i32, bpp: u16, palette: &[[u8; 4]], rows: &[u8]) -> Vec<u8> {
+        let offset = 54 + palette.len() as u32 * 4;
+        let mut data = b"BM".to_vec();
+        data.extend_from_slice(&(offset + rows.len() as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&bpp.to_le_bytes());
+        for n in [0, rows.len() as u32, 0, 0, palette.len() as u32, 0] {
+            data.extend_from_slice(&n.to_le_bytes());
+        }
+        for entry in palette {
+            data.extend_from_slice(&entry[..]);
+        }
+        data.extend_from_slice(rows);
+        data
+    }
+
+    #[test]
+    fn bmp_24_bit_bottom_up() {
+        // rows of 6 bytes padded to 8, the bottom row comes first, colors are bgr
+        let rows = [
+            3, 2, 1, 6, 5, 4, 0, 0,
+            9, 8, 7, 12, 11, 10, 0, 0,
+        ];
+        let image = Image::decode(&bmp(2, 2, 24, &[], &rows[..])[..]).unwrap();
+        assert_eq!(image.colors, [(7, 8, 9), (10, 11, 12), (1, 2, 3), (4, 5, 6)]);
+        assert!(image.alpha.is_none());
+    }
+
+    #[test]
+    fn bmp_32_bit_top_down_with_alpha() {
+        let rows = [3, 2, 1, 255, 6, 5, 4, 128];
+        let image = Image::decode(&bmp(1, -2, 32, &[], &rows[..])[..]).unwrap();
+        assert_eq!(image.colors, [(1, 2, 3), (4, 5, 6)]);
+        assert_eq!(image.alpha, Some(vec![255, 128]));
+        // all zero alpha bytes mean there is no alpha
+        let rows = [3, 2, 1, 0, 6, 5, 4, 0];
+        assert!(Image::decode(&bmp(1, -2, 32, &[], &rows[..])[..]).unwrap().alpha.is_none());
+    }
+
+    #[test]
+    fn bmp_with_palette() {
+        let palette = [[0, 0, 255, 0], [255, 0, 0, 0]];
+        let image = Image::decode(&bmp(3, 1, 8, &palette[..], &[1, 0, 1, 0])[..]).unwrap();
+        assert_eq!(image.colors, [(0, 0, 255), (255, 0, 0), (0, 0, 255)]);
+        // most significant bit first
+        let image = Image::decode(&bmp(10, 1, 1, &palette[..], &[0b1010_0000, 0b0100_0000, 0, 0])[..]).unwrap();
+        let expected: Vec<_> = [1, 0, 1, 0, 0, 0, 0, 0, 0, 1].iter().map(|&i| (palette[i][2], 0, palette[i][0])).collect();
+        assert_eq!(image.colors, expected);
+        let image = Image::decode(&bmp(2, 1, 4, &palette[..], &[0x10, 0, 0, 0])[..]).unwrap();
+        assert_eq!(image.colors, [(0, 0, 255), (255, 0, 0)]);
+    }
+
+    #[test]
+    fn bmp_truncated_or_hostile() {
+        assert_eq!(decode_err(b"BM\0\0"), "truncated bmp");
+        let rows = [3, 2, 1, 0];
+        let full = bmp(1, 1, 24, &[], &rows[..]);
+        assert_eq!(decode_err(&full[..full.len() - 1]), "truncated bmp");
+        assert_eq!(decode_err(&bmp(0, 1, 24, &[], &rows[..])[..]), "malformed bmp header");
+        assert_eq!(decode_err(&bmp(-1, 1, 24, &[], &rows[..])[..]), "malformed bmp header");
+        assert_eq!(decode_err(&bmp(1, 0, 24, &[], &rows[..])[..]), "malformed bmp header");
+        assert_eq!(decode_err(&bmp(1, 1, 16, &[], &rows[..])[..]), "unsupported bits per pixel in bmp");
+        assert_eq!(decode_err(&bmp(i32::MAX, i32::MIN, 32, &[], &rows[..])[..]), "truncated bmp");
+        assert_eq!(decode_err(&bmp(i32::MAX, 1, 1, &[[0; 4]], &rows[..])[..]), "truncated bmp");
+        // index 1 with a one color palette
+        assert_eq!(decode_err(&bmp(1, 1, 8, &[[0; 4]], &[1, 0, 0, 0])[..]), "bmp palette index out of range");
+        let mut compressed = full.clone();
+        compressed[30] = 1;
+        assert_eq!(decode_err(&compressed[..]), "compressed bmp");
+        let mut small_header = full.clone();
+        small_header[14] = 12;
+        assert_eq!(decode_err(&small_header[..]), "unsupported bmp header");
+        // the palette would reach past the end of the file
+        let mut palette = bmp(1, 1, 8, &[[0; 4]], &[0, 0, 0, 0]);
+        palette[46] = 200;
+        palette.truncate(60);
+        assert_eq!(decode_err(&palette[..]), "truncated bmp palette");
+    }
+
+    fn png(width: u32, height: u32, color: png::ColorType, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(data).unwrap();
+        out
+    }
+
+    #[test]
+    fn png_color_types() {
+        let image = Image::decode(&png(2, 1, png::ColorType::Rgb, &[1, 2, 3, 4, 5, 6])[..]).unwrap();
+        assert_eq!(image.colors, [(1, 2, 3), (4, 5, 6)]);
+        assert!(image.alpha.is_none());
+        let image = Image::decode(&png(2, 1, png::ColorType::Rgba, &[1, 2, 3, 255, 4, 5, 6, 7])[..]).unwrap();
+        assert_eq!(image.alpha, Some(vec![255, 7]));
+        let image = Image::decode(&png(1, 2, png::ColorType::Grayscale, &[9, 200])[..]).unwrap();
+        assert_eq!(image.colors, [(9, 9, 9), (200, 200, 200)]);
+        let image = Image::decode(&png(1, 1, png::ColorType::GrayscaleAlpha, &[9, 255])[..]).unwrap();
+        assert_eq!((image.colors[0], image.alpha), ((9, 9, 9), None));
+    }
+
+    #[test]
+    fn png_truncated() {
+        let data = png(4, 4, png::ColorType::Rgb, &[7; 48][..]);
+        for len in [8, 20, 40, data.len() - 13] {
+            assert!(Image::decode(&data[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert_eq!(decode_err(b"GIF89a"), "unknown image format, expected png, bmp or ppm");
+        assert_eq!(decode_err(b""), "unknown image format, expected png, bmp or ppm");
+    }
+}
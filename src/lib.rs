@@ -0,0 +1,47 @@
+pub mod primitive;
+
+pub mod error;
+
+pub mod protocol;
+
+pub mod client;
+
+pub mod batch;
+
+pub mod pool;
+
+pub mod session;
+
+pub mod canvas;
+
+pub mod image;
+
+pub mod dither;
+
+pub mod convolve;
+
+pub mod filter;
+
+pub mod automaton;
+
+pub mod config;
+
+pub mod gradient;
+
+pub mod mandel;
+
+pub mod zoom;
+
+pub mod ifs;
+
+pub mod flame;
+
+pub mod firework;
+
+pub mod show;
+
+pub mod tree;
+
+pub mod magnet;
+
+pub mod paper;
@@ -1,281 +1,47 @@
 use std::io::{Read, Write};
-use std::net::TcpStream;
-use fastrand;
 
-mod primitive;
-use primitive::{Pixel, Rect};
-
-mod mandel;
-
-mod tree;
-use tree::{TreeDraw, DefaultTreeDraw, SymmetricTreeDraw};
-
-mod magnet;
-use magnet::Particle;
-
-mod paper;
-
-#[derive(Debug)]
-struct ServerInfo {
-    width: u32,
-    height: u32,
-    recv_buffer_size: u32,
-    send_buffer_size: u32,
-}
-
-fn decode_u32(data: &[u8]) -> u32 {
-    (data[0] as u32)
-        | ((data[1] as u32) << 8)
-        | ((data[2] as u32) << 16)
-        | ((data[3] as u32) << 24)
-}
-
-fn command_info(stream: &mut TcpStream) -> std::io::Result<ServerInfo> {
-    let mut data = [0u8; 8];
-    data[0] = b'I';
-    stream.write_all(&data[..])?;
-    let mut response = [0u8; 16];
-    stream.read_exact(&mut response[..])?;
-    let width = decode_u32(&response[0..4]);
-    let height = decode_u32(&response[4..8]);
-    let recv_buffer_size = decode_u32(&response[8..12]);
-    let send_buffer_size = decode_u32(&response[12..16]);
-    Ok(ServerInfo { width, height, recv_buffer_size, send_buffer_size })
-}
-
-fn command_print(px: &Pixel, stream: &mut TcpStream) -> std::io::Result<()> {
-    let mut data = [0u8; 8];
-    data[0] = b'P';
-    data[1] = px.x as u8;
-    data[2] = (px.x >> 8) as u8;
-    data[3] = px.y as u8;
-    data[4] = (px.y >> 8) as u8;
-    data[5] = px.color.0;
-    data[6] = px.color.1;
-    data[7] = px.color.2;
-    stream.write_all(&data[..])?;
-    Ok(())
-}
-
-fn encode_rect(rect: Rect, data: &mut [u8]) {
-    // skip first byte
-    data[1] = rect.x as u8;
-    data[2] = (rect.x >> 8) as u8;
-    data[3] = rect.y as u8;
-    data[4] = (rect.y >> 8) as u8;
-    data[5] = rect.w as u8;
-    data[6] = rect.h as u8;
-    data[7] = ((rect.w >> 8) & 0x0f) as u8 | ((rect.h >> 4) & 0xf0) as u8;
-}
-
-fn command_rectangle_get(colors: &mut [(u8, u8, u8)], rect: Rect, stream: &mut TcpStream) -> std::io::Result<()> {
-    assert!(colors.len() == rect.w * rect.h);
-    let mut command: [u8; 8] = [0; 8];
-    command[0] = b'g';
-    encode_rect(rect, &mut command[..]);
-    stream.write_all(&command[..])?;
-    // receive pixels
-    let mut data: Box<[u8; 1024]> = Box::new([0; 1024]);
-    let mut num_bytes_to_read: usize = rect.w * rect.h * 4;
-    let mut pixel_idx = 0;
-    while num_bytes_to_read > 0 {
-        let mut read_size = num_bytes_to_read;
-        if read_size > 1024 {
-            read_size = 1024;
-        }
-        stream.read_exact(&mut data[0..read_size])?;
-        num_bytes_to_read -= read_size;
-        for i in (0..read_size).step_by(4) {
-            colors[pixel_idx] = (data[i + 0], data[i + 1], data[i + 2]);
-            pixel_idx += 1;
-        }
-    }
-    Ok(())
-}
-
-fn command_get(px: &mut Pixel, stream: &mut TcpStream) -> std::io::Result<()> {
-    let mut data = [0u8; 8];
-    data[0] = b'G';
-    data[1] = px.x as u8;
-    data[2] = (px.x >> 8) as u8;
-    data[3] = px.y as u8;
-    data[4] = (px.y >> 8) as u8;
-    data[5] = 0;
-    data[6] = 0;
-    data[7] = 0;
-    stream.write_all(&data[..])?;
-    let mut recv = [0u8; 4];
-    stream.read_exact(&mut recv[..])?;
-    px.color = (recv[0], recv[1], recv[2]);
-    Ok(())
-}
-
-fn command_rectangle_print(colors: &[(u8, u8, u8)], rect: Rect, stream: &mut TcpStream) -> std::io::Result<()> {
-    assert!(colors.len() == rect.w * rect.h);
-    let mut data: Box<[u8; 1024]> = Box::new([0; 1024]);
-    // first round: write actual command
-    data[0] = b'p';
-    encode_rect(rect, &mut data[0..8]);
-    let mut data_fill_start: usize = 8;
-    let mut pixel_idx = 0;
-    while pixel_idx < colors.len() {
-        // fill buffer
-        while data_fill_start <= 1024 - 4 && pixel_idx < colors.len() {
-            let col = colors[pixel_idx];
-            data[data_fill_start] = col.0;
-            data[data_fill_start + 1] = col.1;
-            data[data_fill_start + 2] = col.2;
-            data[data_fill_start + 3] = 0;
-            pixel_idx += 1;
-            data_fill_start += 4;
-        }
-        stream.write_all(&data[0..data_fill_start])?; // buffer may not be full in last round
-        data_fill_start = 0; // reset buffer
-    }
-    Ok(())
-}
-
-fn command_rectangle_fill(color: (u8, u8, u8), rect: Rect, stream: &mut TcpStream) -> std::io::Result<()> {
-    let mut data = [0u8; 12];
-    // first round: write actual command
-    data[0] = b'f';
-    encode_rect(rect, &mut data[0..8]);
-    data[8] = color.0;
-    data[9] = color.1;
-    data[10] = color.2;
-    stream.write_all(&data[..])?;
-    Ok(())
-}
-
-fn add_delta_single(delta: i32, top: i32, bot: i32, base: u8) -> u8 {
-    let m: i32 = delta * top / bot + (base as i32);
-    if m < 0 {
-        0
-    } else if m > 255 {
-        255
-    } else {
-        m as u8
-    }
-}
-
-fn add_delta(delta: (i32, i32, i32), top: i32, bot: i32, base: &mut (u8, u8, u8)) {
-    base.0 = add_delta_single(delta.0, top, bot, base.0);
-    base.1 = add_delta_single(delta.1, top, bot, base.1);
-    base.2 = add_delta_single(delta.2, top, bot, base.2);
-}
-
-fn approx_single(col: u8) -> (u8, i32) {
-    if col < 128 {
-        (0, col as i32)
-    } else {
-        (255, col as i32 - 255)
-    }
-}
-
-fn approx(col: (u8, u8, u8)) -> ((u8, u8, u8), (i32, i32, i32)) {
-    let light: u8 = ((col.0 as i32 + col.1 as i32 + col.2 as i32) / 3) as u8;
-    let (a, d) = approx_single(light);
-    ((a, a, a), (d, d, d))
-}
-
-/*        *  7/16
- * 3/16 5/16 1/16
- */
-
-struct Screen {
-    w: usize,
-    h: usize,
-    colors: Vec<(u8, u8, u8)>,
-}
-
-impl Screen {
-    fn get_neighbor_mut(&mut self, x: usize, y: usize, dx: i32, dy: i32) -> Option<&mut (u8, u8, u8)> {
-        let new_x = if dx >= 0 && x + (dx as usize) < self.w {
-            Some(x + (dx as usize))
-        } else if dx < 0 && (x as i32 + dx) >= 0 {
-            Some((x as i32 + dx) as usize)
-        } else {
-            None
-        };
-        let new_y = if dy >= 0 && y + (dy as usize) < self.h {
-            Some(y + (dy as usize))
-        } else if dy < 0 && (y as i32 + dy) >= 0 {
-            Some((y as i32 + dy) as usize)
-        } else {
-            None
-        };
-        if let (Some(nx), Some(ny)) = (new_x, new_y) {
-            Some(&mut self.colors[ny * self.w + nx])
-        } else {
-            None
-        }
-    }
-}
-
-fn floyd_steinberg_bw(rect: Rect, stream: &mut TcpStream) -> std::io::Result<()> {
-    let mut colors = vec![(0u8, 0u8, 0u8); (rect.w as usize) * (rect.h as usize)];
-    command_rectangle_get(&mut colors[..], rect, stream)?;
-    for y in rect.ys_abs() {
-        for x in rect.xs_abs() {
-            let (new_col, delta) = approx(colors[rect.index_abs(x, y)]);
-            colors[rect.index_abs(x, y)] = new_col;
-            // distribute delta
-            if let Some((xx, yy)) = rect.get_mut_with_delta_abs(x, y, 1, 0) {
-                add_delta(delta, 7, 16, &mut colors[rect.index_abs(xx, yy)]);
-            }
-            if let Some((xx, yy)) = rect.get_mut_with_delta_abs(x, y, -1, 1) {
-                add_delta(delta, 3, 16, &mut colors[rect.index_abs(xx, yy)]);
-            }
-            if let Some((xx, yy)) = rect.get_mut_with_delta_abs(x, y, 0, 1) {
-                add_delta(delta, 5, 16, &mut colors[rect.index_abs(xx, yy)]);
-            }
-            if let Some((xx, yy)) = rect.get_mut_with_delta_abs(x, y, 1, 1) {
-                add_delta(delta, 1, 16, &mut colors[rect.index_abs(xx, yy)]);
-            }
-        }
-    }
-    
-    command_rectangle_print(&colors[..], rect, stream)?;
-    Ok(())
-}
-
-fn clamp(i: i32) -> u8 {
-    if i < 0 {
-        0
-    } else if i > 255 {
-        255
-    } else {
-        i as u8
+use pixelflut::primitive::{average, Pixel, Rect};
+use pixelflut::paper;
+use pixelflut::tree::{TreeDraw, DefaultTreeDraw, SymmetricTreeDraw};
+use pixelflut::magnet::Particle;
+use pixelflut::protocol::{BinaryProtocol, Protocol, ProtocolKind, ServerInfo, TextProtocol};
+use pixelflut::error::PixelflutError;
+use pixelflut::client::PixelflutClient;
+use pixelflut::config::{self, Config, Overrides, Profile};
+use pixelflut::pool::ConnectionPool;
+use pixelflut::session::{Backoff, Pacer, Session};
+use pixelflut::canvas::{Canvas, Screen};
+use pixelflut::image::{self, Image};
+use pixelflut::dither::{self, OrderedDither, Palette};
+use pixelflut::convolve::{Accumulator, Preset};
+use pixelflut::automaton::Runner;
+use pixelflut::zoom::Zoom;
+use pixelflut::show::Show;
+
+mod cli;
+use cli::{Args, Command};
+
+fn dither<S: Read + Write, P: Protocol>(client: &mut PixelflutClient<S, P>, rect: Rect, args: &Args) -> Result<(), PixelflutError> {
+    let mut screen = Screen::new(rect.w, rect.h);
+    client.rectangle_get(&mut screen.colors[..], rect)?;
+    let palette = match (&args.palette, args.palette_size) {
+        (Some(palette), _) => palette.clone(),
+        (None, Some(n)) => args.quantizer.palette(&screen.colors[..], n),
+        (None, None) => Palette::black_and_white(),
+    };
+    match &args.threshold_map {
+        Some(map) => OrderedDither::new(map.clone(), palette).apply(&mut screen, rect),
+        None => dither::diffuse(&mut screen, &palette, args.kernel, args.serpentine),
     }
+    client.rectangle_print(&screen.colors[..], rect)
 }
 
-fn kernel_3x3(rect: Rect, kernel: [(i32, i32); 9], stream: &mut TcpStream) -> std::io::Result<()> {
-    let mut colors = vec![(0u8, 0u8, 0u8); (rect.w as usize) * (rect.h as usize)];
-    let mut new_colors = vec![(0u8, 0u8, 0u8); (rect.w as usize) * (rect.h as usize)];
-    command_rectangle_get(&mut colors[..], rect, stream)?;
-
-    for y in rect.ys_abs() {
-        for x in rect.xs_abs() {
-            let mut new_color = (0i32, 0i32, 0i32);
-            let mut index = 0;
-            for dy in -1..=1 {
-                for dx in -1..=1 {
-                    let (top, bottom) = kernel[index];
-                    if let Some((xx, yy)) = rect.get_mut_with_delta_abs(x, y, dx, dy) {
-                        let color = colors[rect.index_abs(xx, yy)];
-                        new_color.0 += color.0 as i32 * top / bottom;
-                        new_color.1 += color.1 as i32 * top / bottom;
-                        new_color.2 += color.2 as i32 * top / bottom;
-                    }
-                    index += 1;
-                }
-            }
-            new_colors[rect.index_abs(x, y)] = (clamp(new_color.0), clamp(new_color.1), clamp(new_color.2));
-        }
-    }
-
-    command_rectangle_print(&new_colors[..], rect, stream)?;
-    Ok(())
+fn convolve<S: Read + Write, P: Protocol>(client: &mut PixelflutClient<S, P>, rect: Rect, preset: Preset, args: &Args) -> Result<(), PixelflutError> {
+    let mut screen = Screen::new(rect.w, rect.h);
+    client.rectangle_get(&mut screen.colors[..], rect)?;
+    let accumulator = if args.fixed_point { Accumulator::Fixed } else { Accumulator::Float };
+    let result = preset.apply(&screen, args.edge, accumulator);
+    client.rectangle_print(&result.colors[..], rect)
 }
 
 fn draw_circle_eighth(center: (usize, usize), radius: usize) -> Vec<(usize, usize)> {
@@ -288,11 +54,11 @@ fn draw_circle_eighth(center: (usize, usize), radius: usize) -> Vec<(usize, usiz
         let dx1 = (x + 1) - center.0;
         let dy1 = y - center.1;
         let d1 = dx1 * dx1 + dy1 * dy1;
-        let discrepancy1 = if d1 < rsq { rsq - d1 } else { d1 - rsq};
+        let discrepancy1 = d1.abs_diff(rsq);
         let dx2 = (x + 1) - center.0;
         let dy2 = (y - 1) - center.1;
         let d2 = dx2 * dx2 + dy2 * dy2;
-        let discrepancy2 = if d2 < rsq { rsq - d2 } else { d2 - rsq};
+        let discrepancy2 = d2.abs_diff(rsq);
         if discrepancy1 <= discrepancy2 {
             x += 1;
             from_c_x += 1;
@@ -326,178 +92,238 @@ fn draw_circle(center: (usize, usize), radius: usize) -> Vec<(usize, usize)> {
     coords
 }
 
-// TODO worm
-
-fn main() -> std::io::Result<()> {
-    let mut stream = TcpStream::connect("127.0.0.1:1337")?;
-
-    let info = command_info(&mut stream)?;
- 
+fn screen_rect(info: ServerInfo) -> Rect {
+    Rect { x: 0, y: 0, w: info.width as usize, h: info.height as usize }
+}
 
-    /*
-    for x in 100..500 {
-        for pixel in paper::dot_at(x, 100, (10, 10, 10)) {
-            command_print(&pixel, &mut stream)?;
+// with more than one connection the pixels are spread over a pool of extra connections
+fn blast<S: Read + Write + Send, P: Protocol + Clone + Send>(session: &mut Session<S, P>, pixels: &[Pixel], profile: &Profile, args: &Args) -> Result<(), PixelflutError> {
+    let dithered: Vec<Pixel>;
+    let pixels = match args.ordered_dither() {
+        Some(ordered) => {
+            dithered = pixels.iter().map(|px| ordered.pixel(px)).collect();
+            &dithered[..]
         }
+        None => pixels,
+    };
+    if profile.connections <= 1 {
+        session.write_pixels(pixels.iter().copied(), profile.batch_size)?;
+        return Ok(());
     }
-    */
+    // the session draws the first shard itself, the pool connections reconnect on their own
+    let stats = ConnectionPool::connect(session, profile.connections - 1, profile.batch_size)?
+        .draw(session, pixels, args.sharding)?;
+    eprintln!("{} connections: {}", profile.connections, stats);
+    Ok(())
+}
 
-    /*
-    let dtd = SymmetricTreeDraw;
-    for pixels in dtd.steps(info.width as usize, info.height as usize) {
-        for pixel in pixels {
-            command_print(&pixel, &mut stream)?;
-        }
-    }*/
+fn draw_tree<S: Read + Write + Send, P: Protocol + Clone + Send>(session: &mut Session<S, P>, symmetric: bool, profile: &Profile, args: &Args) -> Result<(), PixelflutError> {
+    let info = session.info();
+    let steps = if symmetric {
+        SymmetricTreeDraw.steps(info.width as usize, info.height as usize)
+    } else {
+        DefaultTreeDraw.steps(info.width as usize, info.height as usize)
+    };
+    let pixels: Vec<Pixel> = steps.into_iter().flatten().collect();
+    blast(session, &pixels[..], profile, args)
+}
 
-    /*
-    let mut worms = Vec::new();
-    let mut worms2 = Vec::new();
-    worms.push(Worm::from((info.width as usize / 2) as f64, (info.height as usize - 1) as f64, std::f64::consts::PI / 8.0, 3.0, 20, (200, 200, 200)));
-    while worms.len() > 0 {
-        for mut worm in worms.drain(..) {
-            if let Some(WormResult { mut new_worms, pixels }) = worm.step::<tree::Mutate2>(info.width as usize, info.height as usize) {
-                for px in &pixels {
-                    command_print(px, &mut stream)?;
-                }
-                worms2.push(worm);
-                worms2.append(&mut new_worms);
-            }
-        }
-        // the original worms is empty now
-        std::mem::swap(&mut worms, &mut worms2);
+fn threads(args: &Args) -> usize {
+    args.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+fn draw_mandel<S: Read + Write + Send, P: Protocol + Clone + Send>(session: &mut Session<S, P>, rect: Rect, profile: &Profile, args: &Args) -> Result<(), PixelflutError> {
+    let mut fractal = args.fractal.clone();
+    if let Some(color) = args.color {
+        fractal.inside = color;
+    }
+    let progression = match args.progression {
+        Some(progression) => progression,
+        None => return blast(session, &fractal.render(rect)[..], profile, args),
+    };
+    let threads = threads(args);
+    let ordered = args.ordered_dither();
+    for pixels in fractal.progressive(rect, progression, threads) {
+        let pixels = pixels.into_iter().map(|px| ordered.as_ref().map_or(px, |o| o.pixel(&px)));
+        session.write_pixels(pixels, profile.batch_size)?;
     }
+    Ok(())
+}
 
-    let size = 15;
-    let mut colors = vec![(0u8, 0u8, 0u8); size*size];
-    loop {
-        let rx = fastrand::usize(0..(info.width as usize));
-        let ry = fastrand::usize(0..(info.height as usize));
-        let rect = Rect { x: rx, y: ry, w: size, h: size };
-        command_rectangle_get(&mut colors[..], rect, &mut stream)?;
-        let mut rr: usize = 0;
-        let mut gg: usize = 0;
-        let mut bb: usize = 0;
-        for color in &colors {
-            rr += color.0 as usize;
-            gg += color.1 as usize;
-            bb += color.2 as usize;
+// every frame only uploads the pixels that changed since the previous one
+fn zoom<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, rect: Rect, args: &Args, batch_size: usize) -> Result<(), PixelflutError> {
+    let mut fractal = args.fractal.clone();
+    if let Some(color) = args.color {
+        fractal.inside = color;
+    }
+    let zoom = Zoom::new(fractal, args.center, args.zoom_factor).with_threads(threads(args));
+    let frames = zoom.frames(rect.h);
+    let mut canvas = Canvas::new(rect);
+    let mut pacer = Pacer::new(args.rate);
+    let mut reconnects = session.reconnects();
+    let mut frame = 0;
+    while args.count.is_none_or(|n| frame < n) {
+        canvas.screen_mut().colors = zoom.frame(frame % frames, rect.w, rect.h);
+        session.run(|client| canvas.upload(client, batch_size))?;
+        // a new connection may mean a restarted server, so send everything next time
+        if session.reconnects() != reconnects {
+            reconnects = session.reconnects();
+            canvas.invalidate();
         }
-        rr /= size * size;
-        gg /= size * size;
-        bb /= size * size;
-        command_rectangle_fill((rr as u8, gg as u8, bb as u8), rect, &mut stream)?;
+        frame += 1;
+        pacer.wait();
     }
-    */
+    Ok(())
+}
+
+fn draw_flame<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, n: usize, rect: Rect, args: &Args) -> Result<(), PixelflutError> {
+    let screen = args.flame.render(n, rect.w, rect.h);
+    session.run(|client| client.rectangle_print(&screen.colors[..], rect))
+}
+
+fn draw_ifs<S: Read + Write + Send, P: Protocol + Clone + Send>(session: &mut Session<S, P>, n: usize, rect: Rect, profile: &Profile, args: &Args) -> Result<(), PixelflutError> {
+    let pixels = args.ifs.render(n, rect);
+    blast(session, &pixels[..], profile, args)
+}
 
-    /*
+fn draw_fireworks<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, n: usize, rect: Rect, args: &Args, batch_size: usize) -> Result<(), PixelflutError> {
+    Show::new(rect)
+        .with_fps(args.rate.unwrap_or(30.0))
+        .with_launch_rate(args.launch_rate)
+        .run(session, Some(n), batch_size)
+}
+
+fn draw_magnet<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, nob: usize, batch_size: usize) -> Result<(), PixelflutError> {
+    let info = session.info();
     let create_obstacle = || (fastrand::f64() * info.width as f64, fastrand::f64() * info.height as f64);
-    let nob = 10;
     let obstacles: Vec<(f64, f64)> = (0..nob).map(|_| create_obstacle()).collect();
-    //let obstacles: Vec<(f64, f64)> = vec![(512.0, 100.0), (100.0, 512.0), (924.0, 512.0), (512.0, 924.0)];
-    //for (ox, oy) in &obstacles {
-    //    command_print(&Pixel { x: *ox as usize, y: *oy as usize, color: (255,0,0) }, &mut stream)?;
-    //}
-    for nn in 0..nob {
+    for obstacle in &obstacles {
         let n = 20;
         for i in 0..n {
             let dx = (2.0 * std::f64::consts::PI * i as f64 / n as f64).cos();
             let dy = -(2.0 * std::f64::consts::PI * i as f64 / n as f64).sin();
-            let mut p = Particle::stationary(8.0*dx + obstacles[nn].0, 8.0*dy + obstacles[nn].1);
-            let mut skip: Option<usize> = None;
+            let mut p = Particle::stationary(8.0*dx + obstacle.0, 8.0*dy + obstacle.1);
             let delta_stop = fastrand::f64() * 20.0;
-            let mut steps: usize = 0;
+            let mut line = Vec::new();
             while p.x >= 0.0 && p.y >= 0.0 && p.x <= info.width as f64 && p.y <= info.height as f64 {
                 p.step(&obstacles[..]);
                 let toc_x = p.x - info.width as f64 / 2.0;
                 let toc_y = p.y - info.height as f64 / 2.0;
-                skip = None;
-                /*
-                skip = match skip {
-                    Some(i) if i > 0 => Some(i - 1),
-                    Some(_) => None,
-                    None => {
-                        if fastrand::f64() < 0.1 {
-                            Some(fastrand::usize(2..8))
-                        } else {
-                            None
-                        }
-                    },
-                };
-                */
-                if (toc_x*toc_x + toc_y*toc_y).sqrt() <= info.width as f64 / 2.0 - delta_stop && skip.is_none() {
-                    // let c = if steps > 255*2 { 0u8 } else { (255 - steps / 2) as u8 };
-                    for pixel in paper::dot_at(p.x as usize, p.y as usize, (10, 10, 10)) {
-                        command_print(&pixel, &mut stream)?;
-                    }
-                    // command_print(&Pixel { x: p.x as usize, y: p.y as usize, color: (c,c,c) }, &mut stream)?;
+                if (toc_x*toc_x + toc_y*toc_y).sqrt() <= info.width as f64 / 2.0 - delta_stop {
+                    line.extend(paper::dot_at(p.x as usize, p.y as usize, (10, 10, 10)));
                 }
-                steps += 1;
-                // std::thread::sleep(std::time::Duration::from_millis(1));
             }
+            session.write_pixels(line, batch_size)?;
         }
     }
-    */
-    /*
-    let mut rad: usize = 50;
+    Ok(())
+}
+
+fn draw_circles<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, batch_size: usize) -> Result<(), PixelflutError> {
+    let info = session.info();
+    let center = (info.width as usize / 2, info.height as usize / 2);
+    let max_rad = center.0.min(center.1);
+    let mut rad: usize = max_rad / 4;
     for _ in 0..10 {
-        for (x, y) in draw_circle((512, 512), rad) {
-            for pixel in paper::dot_at(x, y, (10, 10, 10)) {
-                command_print(&pixel, &mut stream)?;
-            }
-        }
-        rad = rad + (200 - rad) * 3 / 10;
+        let circle = draw_circle(center, rad).into_iter().flat_map(|(x, y)| paper::dot_at(x, y, (10, 10, 10)));
+        session.write_pixels(circle, batch_size)?;
+        rad = rad + (max_rad - rad) * 3 / 10;
     }
-    */
-    // command_rectangle_fill((0, 0, 0), Rect { x: 0, y: 0, w: info.width as usize, h: info.height as usize }, &mut stream)?;
-    /*
-    command_print(&Pixel { x: 1, y: 1, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 4, y: 1, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 5, y: 2, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 5, y: 3, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 5, y: 4, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 4, y: 4, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 3, y: 4, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 2, y: 4, color: (255,255,255) }, &mut stream)?;
-    command_print(&Pixel { x: 1, y: 3, color: (255,255,255) }, &mut stream)?;
-    */
-        /*
-    for y in 0..info.height as usize {
-        for x in 0..info.width as usize {
-            if fastrand::bool() {
-                command_print(&Pixel { x, y, color: (255,255,255) }, &mut stream)?;
-            }
-        }
+    Ok(())
+}
+
+fn blur_square<S: Read + Write, P: Protocol>(client: &mut PixelflutClient<S, P>, rect: Rect) -> Result<(), PixelflutError> {
+    let mut colors = vec![(0u8, 0u8, 0u8); rect.w * rect.h];
+    client.rectangle_get(&mut colors[..], rect)?;
+    client.rectangle_fill(average(&colors[..]), rect)
+}
+
+fn blur<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, size: usize, iterations: Option<usize>) -> Result<(), PixelflutError> {
+    let mut i = 0;
+    while iterations.is_none_or(|n| i < n) {
+        let info = session.info();
+        let rx = fastrand::usize(0..(info.width as usize).saturating_sub(size).max(1));
+        let ry = fastrand::usize(0..(info.height as usize).saturating_sub(size).max(1));
+        let rect = Rect { x: rx, y: ry, w: size, h: size };
+        session.run(|client| blur_square(client, rect))?;
+        i += 1;
+    }
+    Ok(())
+}
+
+fn life<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, rect: Rect, args: &Args, batch_size: usize) -> Result<(), PixelflutError> {
+    let mut runner = Runner::new(rect, args.rule, args.topology, args.cells)
+        .with_rate(args.rate)
+        .with_react(args.react);
+    if args.randomize {
+        runner.randomize(session, 0.5, batch_size)?;
+    } else {
+        runner.fetch(session)?;
     }
-        */
-    loop {
-        let mut colors: Vec<(u8, u8, u8)> = vec![(0, 0, 0); info.width as usize * info.height as usize];
-        command_rectangle_get(&mut colors[..], Rect { x: 0, y: 0, w: info.width as usize, h: info.height as usize }, &mut stream)?;
+    runner.run(session, args.count, batch_size)
+}
 
-        let neighbor_coords: [(isize, isize); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
-        let alive = |(r, g, b)| r as usize + g as usize + b as usize >= 128*3;
-        let neighbors_alive = |(x, y), colors: &[(u8, u8, u8)]| neighbor_coords
-                .iter()
-                .map(|(dx, dy)| (x as isize + dx, y as isize + dy))
-                .filter(|(nx, ny)| *nx >= 0 && *ny >= 0 && *nx < info.width as isize && *ny < info.height as isize)
-                .map(|(nx, ny)| colors[ny as usize * info.width as usize + nx as usize])
-                .filter(|col| alive(*col))
-                .count();
+fn draw_image<S: Read + Write, P: Protocol + Clone>(session: &mut Session<S, P>, args: &Args) -> Result<(), PixelflutError> {
+    let image = Image::load(args.file.as_deref().expect("checked by parse_args"))?;
+    let rect = args.rect.unwrap_or_else(|| image.fit(screen_rect(session.info())));
+    session.run(|client| image::draw(client, &image, rect, args.filter))
+}
 
-        let mut colors2 = colors.clone();
-        for y in 0..info.height as usize {
-            for x in 0..info.width as usize {
-                let na = neighbors_alive((x as isize, y as isize), &colors[..]);
-                // alive after:
-                if (alive(colors[y * info.width as usize + x]) && na >= 2 && na <= 3) || (!alive(colors[y * info.width as usize + x]) && na == 3) {
-                    colors2[y * info.width as usize + x] = (255, 255, 255);
-                } else {
-                    colors2[y * info.width as usize + x] = (0, 0, 0);
-                }
-            }
+fn run<S: Read + Write + Send, P: Protocol + Clone + Send>(session: &mut Session<S, P>, args: &Args, profile: &Profile) -> Result<(), PixelflutError> {
+    let rect = args.rect.unwrap_or_else(|| screen_rect(session.info()));
+    let batch_size = profile.batch_size;
+    match args.command {
+        Command::Tree => draw_tree(session, args.symmetric, profile, args),
+        Command::Mandel => draw_mandel(session, rect, profile, args),
+        Command::Ifs => draw_ifs(session, args.count.unwrap_or(100000), rect, profile, args),
+        Command::Flame => draw_flame(session, args.count.unwrap_or(1000000), rect, args),
+        Command::Firework => draw_fireworks(session, args.count.unwrap_or(10), rect, args, batch_size),
+        Command::Magnet => draw_magnet(session, args.count.unwrap_or(10), batch_size),
+        Command::Life => life(session, rect, args, batch_size),
+        Command::Dither => session.run(|client| dither(client, rect, args)),
+        Command::Sharpen => session.run(|client| convolve(client, rect, Preset::Sharpen, args)),
+        Command::Convolve => session.run(|client| convolve(client, rect, args.preset, args)),
+        Command::Zoom => zoom(session, rect, args, batch_size),
+        Command::Filter => session.run(|client| args.filters.as_ref().expect("checked by parse_args").run(client, rect)),
+        Command::Blur => blur(session, args.size.unwrap_or(15), args.count),
+        Command::Fill => session.run(|client| client.rectangle_fill(args.color.unwrap_or((0, 0, 0)), rect)),
+        Command::Circles => draw_circles(session, batch_size),
+        Command::Image => draw_image(session, args),
+    }
+}
+
+// command line > environment > profile from the config file
+fn load_profile(args: &Args) -> Result<Profile, String> {
+    let config = match args.config.clone().or_else(config::default_config_path) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    let env_profile = std::env::var("PIXELFLUT_PROFILE").ok();
+    config.resolve(args.profile.as_deref().or(env_profile.as_deref()), &Overrides::from_env()?, &args.overrides)
+}
+
+fn main() -> Result<(), PixelflutError> {
+    let args = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let profile = match load_profile(&args) {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
         }
-        command_rectangle_print(&colors2[..], Rect { x: 0, y: 0, w: info.width as usize, h: info.height as usize }, &mut stream)?;
+    };
+    if let Some(seed) = args.seed {
+        fastrand::seed(seed);
     }
 
-    Ok(())
+    let backoff = Backoff { max_attempts: args.retries, ..Backoff::default() };
+    match profile.protocol {
+        ProtocolKind::Binary => run(&mut Session::tcp(&profile.host, profile.port, BinaryProtocol, backoff)?, &args, &profile),
+        ProtocolKind::Text => run(&mut Session::tcp(&profile.host, profile.port, TextProtocol, backoff)?, &args, &profile),
+    }
 }
@@ -1,21 +1,71 @@
-use crate::Pixel;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
 
-#[derive(Copy, Clone, Debug)]
-struct Complex {
-    r: f64,
-    c: f64,
+use crate::gradient::Gradient;
+use crate::primitive::{Pixel, Rect};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Complex {
+    pub r: f64,
+    pub c: f64,
 }
 
 impl Complex {
-    fn from(r: f64, c: f64) -> Self {
+    pub fn from(r: f64, c: f64) -> Self {
         Self { r, c }
     }
 
+    // by squaring, log2(n) steps
+    pub fn powi(self, mut n: u32) -> Self {
+        let mut result = Complex::from(1.0, 0.0);
+        let mut base = self;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n >>= 1;
+        }
+        result
+    }
+
+    pub fn scale(self, factor: f64) -> Self {
+        Self { r: self.r * factor, c: self.c * factor }
+    }
+
+    pub fn conj(self) -> Self {
+        Self { r: self.r, c: -self.c }
+    }
+
+    pub fn norm_sqr(self) -> f64 {
+        self.r * self.r + self.c * self.c
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Self;
+
     fn add(mut self, other: Self) -> Self {
         self.r += other.r;
         self.c += other.c;
         self
     }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Self;
+
+    fn sub(mut self, other: Self) -> Self {
+        self.r -= other.r;
+        self.c -= other.c;
+        self
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Self;
 
     fn mul(self, other: Self) -> Self {
         Self {
@@ -25,43 +75,610 @@ impl Complex {
     }
 }
 
-fn mandel_iter(zn: Complex, c: Complex) -> Complex {
-    zn.mul(zn).add(c)
+// one step z -> f(z, c) of an escape time fractal
+pub trait Formula: Debug + Send + Sync {
+    // z0 and c for a point of the plane
+    fn start(&self, point: Complex) -> (Complex, Complex) {
+        (Complex::from(0.0, 0.0), point)
+    }
+
+    fn iterate(&self, z: Complex, c: Complex) -> Complex;
+
+    // |z| grows to about this power each step, used for smooth coloring
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    // where the whole fractal can be seen
+    fn view(&self) -> View {
+        View::default()
+    }
+}
+
+// z^2 + c
+#[derive(Debug, Copy, Clone)]
+pub struct Mandelbrot;
+
+impl Formula for Mandelbrot {
+    fn iterate(&self, z: Complex, c: Complex) -> Complex {
+        z * z + c
+    }
+}
+
+// z^2 + c with a fixed c, the point of the plane is z0
+#[derive(Debug, Copy, Clone)]
+pub struct Julia {
+    pub c: Complex,
+}
+
+impl Default for Julia {
+    fn default() -> Self {
+        Self { c: Complex::from(-0.8, 0.156) }
+    }
+}
+
+impl Formula for Julia {
+    fn start(&self, point: Complex) -> (Complex, Complex) {
+        (point, self.c)
+    }
+
+    fn iterate(&self, z: Complex, c: Complex) -> Complex {
+        z * z + c
+    }
+
+    fn view(&self) -> View {
+        View { rmin: -1.6, rmax: 1.6, imin: -1.2, imax: 1.2 }
+    }
+}
+
+// (|re z| + i |im z|)^2 + c
+#[derive(Debug, Copy, Clone)]
+pub struct BurningShip;
+
+impl Formula for BurningShip {
+    fn iterate(&self, z: Complex, c: Complex) -> Complex {
+        let z = Complex::from(z.r.abs(), z.c.abs());
+        z * z + c
+    }
+
+    // upside down, so the ship stands upright on the screen
+    fn view(&self) -> View {
+        View { rmin: -2.2, rmax: 1.3, imin: 1.0, imax: -2.0 }
+    }
+}
+
+// conj(z)^2 + c
+#[derive(Debug, Copy, Clone)]
+pub struct Tricorn;
+
+impl Formula for Tricorn {
+    fn iterate(&self, z: Complex, c: Complex) -> Complex {
+        let z = z.conj();
+        z * z + c
+    }
+
+    fn view(&self) -> View {
+        View { rmin: -2.2, rmax: 1.8, imin: -1.5, imax: 1.5 }
+    }
+}
+
+// higher powers escape after the first iteration anywhere outside the unit
+// circle and only cost time
+pub const MAX_POWER: u32 = 64;
+
+// z^power + c
+#[derive(Debug, Copy, Clone)]
+pub struct Multibrot {
+    pub power: u32,
+}
+
+impl Formula for Multibrot {
+    fn iterate(&self, z: Complex, c: Complex) -> Complex {
+        z.powi(self.power) + c
+    }
+
+    fn degree(&self) -> f64 {
+        self.power as f64
+    }
+
+    fn view(&self) -> View {
+        View { rmin: -1.6, rmax: 1.6, imin: -1.5, imax: 1.5 }
+    }
+}
+
+fn parameter<T: std::str::FromStr>(spec: &str, parameters: &[&str], i: usize, default: T) -> Result<T, String> {
+    match parameters.get(i) {
+        Some(p) => p.parse().map_err(|_| format!("invalid parameter {:?} in formula {:?}", p, spec)),
+        None => Ok(default),
+    }
+}
+
+// mandelbrot, julia[:RE:IM], burning-ship, tricorn or multibrot[:POWER]
+pub fn parse_formula(s: &str) -> Result<Arc<dyn Formula>, String> {
+    let mut parts = s.split(':');
+    let name = parts.next().unwrap_or_default();
+    let parameters: Vec<&str> = parts.collect();
+    let formula: Arc<dyn Formula> = match (name, parameters.len()) {
+        ("mandelbrot", 0) => Arc::new(Mandelbrot),
+        ("julia", 0) => Arc::new(Julia::default()),
+        ("julia", 2) => Arc::new(Julia {
+            c: Complex::from(parameter(s, &parameters, 0, 0.0)?, parameter(s, &parameters, 1, 0.0)?),
+        }),
+        ("burning-ship", 0) => Arc::new(BurningShip),
+        ("tricorn", 0) => Arc::new(Tricorn),
+        ("multibrot", 0 | 1) => {
+            let power = parameter(s, &parameters, 0, 3)?;
+            if !(2..=MAX_POWER).contains(&power) {
+                return Err(format!("formula {:?} needs a power from 2 to {}", s, MAX_POWER));
+            }
+            Arc::new(Multibrot { power })
+        }
+        _ => {
+            return Err(format!(
+                "unknown formula {:?}, expected mandelbrot, julia[:RE:IM], burning-ship, tricorn or multibrot[:POWER]",
+                s
+            ))
+        }
+    };
+    Ok(formula)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Coloring {
+    // whole iteration counts, shows bands
+    Plain,
+    // fractional iteration counts, no bands
+    Smooth,
+    // smooth counts spread evenly over the gradient by their rank
+    Histogram,
+}
+
+impl std::str::FromStr for Coloring {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Coloring::Plain),
+            "smooth" => Ok(Coloring::Smooth),
+            "histogram" => Ok(Coloring::Histogram),
+            _ => Err(format!("unknown coloring {:?}, expected plain, smooth or histogram", s)),
+        }
+    }
+}
+
+// the part of the complex plane that is drawn
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct View {
+    pub rmin: f64,
+    pub rmax: f64,
+    pub imin: f64,
+    pub imax: f64,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self { rmin: -2.0, rmax: 1.0, imin: -1.5, imax: 1.5 }
+    }
+}
+
+impl std::str::FromStr for View {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid view {:?}, expected RMIN,RMAX,IMIN,IMAX", s);
+        let parts: Vec<f64> = s
+            .split(',')
+            .map(|p| p.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        match parts[..] {
+            [rmin, rmax, imin, imax] if rmin != rmax && imin != imax => Ok(View { rmin, rmax, imin, imax }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Fractal {
+    pub formula: Arc<dyn Formula>,
+    pub view: View,
+    pub iterations: usize,
+    // large radii make smooth coloring more accurate
+    pub escape_radius: f64,
+    pub gradient: Gradient,
+    // color of points that never escape
+    pub inside: (u8, u8, u8),
+    pub coloring: Coloring,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Self::new(Arc::new(Mandelbrot))
+    }
+}
+
+impl Fractal {
+    pub fn new(formula: Arc<dyn Formula>) -> Self {
+        Self {
+            view: formula.view(),
+            formula,
+            iterations: 256,
+            escape_radius: 256.0,
+            gradient: Gradient::gray(),
+            inside: (0, 0, 0),
+            coloring: Coloring::Smooth,
+        }
+    }
+
+    // y grows downwards on the screen but upwards in the plane
+    fn coordinate(&self, x: usize, y: usize, w: usize, h: usize) -> Complex {
+        let view = &self.view;
+        Complex::from(
+            view.rmin + (view.rmax - view.rmin) * (x as f64 / w as f64),
+            view.imin + (view.imax - view.imin) * ((h - (y + 1)) as f64 / h as f64),
+        )
+    }
+
+    // number of iterations until the point escapes, None if it stays bounded
+    fn escape(&self, point: Complex) -> Option<f64> {
+        let radius_sqr = self.escape_radius * self.escape_radius;
+        let (mut zn, c) = self.formula.start(point);
+        for i in 1..=self.iterations {
+            zn = self.formula.iterate(zn, c);
+            let norm_sqr = zn.norm_sqr();
+            if norm_sqr > radius_sqr {
+                return Some(self.escape_time(i, norm_sqr));
+            }
+        }
+        None
+    }
+
+    // escape time of a point whose |z|^2 was norm_sqr after escaping in iteration i
+    pub fn escape_time(&self, i: usize, norm_sqr: f64) -> f64 {
+        if self.coloring == Coloring::Plain {
+            return i as f64;
+        }
+        // |z| is between R and R^degree right after escaping, this moves i by less than one
+        let ratio = norm_sqr.ln() / (2.0 * self.escape_radius.ln());
+        (i as f64 - ratio.log(self.formula.degree())).max(0.0)
+    }
+
+    // row-major escape times of a w x h image of the view
+    pub fn escape_times(&self, w: usize, h: usize) -> Vec<Option<f64>> {
+        let mut times = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                times.push(self.escape(self.coordinate(x, y, w, h)));
+            }
+        }
+        times
+    }
+
+    // histogram coloring ranks against the escape times of samples
+    fn scale(&self, samples: &[Option<f64>]) -> Scale {
+        match self.coloring {
+            Coloring::Plain | Coloring::Smooth => Scale::Linear(self.iterations.max(1) as f64),
+            Coloring::Histogram => {
+                let mut sorted: Vec<f64> = samples.iter().flatten().copied().collect();
+                sorted.sort_by(f64::total_cmp);
+                Scale::Ranks(sorted)
+            }
+        }
+    }
+
+    fn color(&self, scale: &Scale, time: Option<f64>) -> (u8, u8, u8) {
+        match time {
+            Some(t) => self.gradient.at(scale.position(t)),
+            None => self.inside,
+        }
+    }
+
+    pub fn colorize(&self, times: &[Option<f64>]) -> Vec<(u8, u8, u8)> {
+        let scale = self.scale(times);
+        times.iter().map(|&t| self.color(&scale, t)).collect()
+    }
+
+    pub fn render(&self, rect: Rect) -> Vec<Pixel> {
+        let colors = self.colorize(&self.escape_times(rect.w, rect.h)[..]);
+        colors
+            .into_iter()
+            .enumerate()
+            .map(|(i, color)| Pixel { x: rect.x + i % rect.w, y: rect.y + i / rect.w, color })
+            .collect()
+    }
+}
+
+// maps escape times to positions on the gradient
+enum Scale {
+    // divided by the number of iterations
+    Linear(f64),
+    // rank among sorted samples
+    Ranks(Vec<f64>),
+}
+
+impl Scale {
+    fn position(&self, t: f64) -> f64 {
+        match self {
+            Scale::Linear(n) => t / n,
+            Scale::Ranks(sorted) => sorted.partition_point(|&s| s < t) as f64 / sorted.len().max(1) as f64,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Progression {
+    // 16x16 blocks first, then halved with every pass
+    Coarse,
+    // the seven sparse passes of interlaced png
+    Adam7,
+}
+
+impl std::str::FromStr for Progression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "coarse" => Ok(Progression::Coarse),
+            "adam7" => Ok(Progression::Adam7),
+            _ => Err(format!("unknown progression {:?}, expected coarse or adam7", s)),
+        }
+    }
+}
+
+const COARSE_BLOCK: usize = 16;
+
+// rows of a pass that one job renders
+const BAND_HEIGHT: usize = 32;
+
+// every dx-th column of every dy-th row, starting at (x, y)
+#[derive(Debug, Copy, Clone)]
+struct Lattice {
+    x: usize,
+    y: usize,
+    dx: usize,
+    dy: usize,
+}
+
+// samples of one pass, each drawn as a block until a later pass refines it
+#[derive(Debug, Clone)]
+struct Pass {
+    lattices: Vec<Lattice>,
+    block_w: usize,
+    block_h: usize,
 }
 
-fn mandel_exceeds(c: Complex, max_iter: usize, thresh: f64) -> Option<usize> {
-    let mut zn = Complex::from(0.0, 0.0);
-    for i in 0..max_iter {
-        zn = mandel_iter(zn, c);
-        if zn.r * zn.r + zn.c * zn.c > thresh {
-            return Some(i);
+impl Progression {
+    fn passes(self) -> Vec<Pass> {
+        let lattice = |x, y, dx, dy| Lattice { x, y, dx, dy };
+        match self {
+            Progression::Coarse => {
+                let mut passes = vec![Pass {
+                    lattices: vec![lattice(0, 0, COARSE_BLOCK, COARSE_BLOCK)],
+                    block_w: COARSE_BLOCK,
+                    block_h: COARSE_BLOCK,
+                }];
+                let mut size = COARSE_BLOCK / 2;
+                while size >= 1 {
+                    // the points of the finer grid that the previous pass didn't have
+                    passes.push(Pass {
+                        lattices: vec![
+                            lattice(size, 0, 2 * size, 2 * size),
+                            lattice(0, size, 2 * size, 2 * size),
+                            lattice(size, size, 2 * size, 2 * size),
+                        ],
+                        block_w: size,
+                        block_h: size,
+                    });
+                    size /= 2;
+                }
+                passes
+            }
+            Progression::Adam7 => [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)]
+                .iter()
+                .map(|&(x, y, dx, dy)| Pass { lattices: vec![lattice(x, y, dx, dy)], block_w: 1, block_h: 1 })
+                .collect(),
         }
     }
-    None
 }
 
-pub fn draw(rmin: f64, rmax: f64, imin: f64, imax: f64, xsize: usize, ysize: usize) -> Vec<Pixel> {
-    let mut v = Vec::new();
-    for x in 0..xsize {
-        for y in 0..ysize {
-            let coord = Complex::from(
-                rmin + (rmax - rmin) * (x as f64 / xsize as f64),
-                imin + (imax - imin) * ((ysize - (y + 1)) as f64 / ysize as f64),
-            );
-            if let Some(a) = mandel_exceeds(coord, 20, 100000.0) {
-                v.push(Pixel {
-                    x,
-                    y,
-                    color: (10 * a as u8, 10 * a as u8, 10 * a as u8),
-                })
-            } else {
-                v.push(Pixel {
-                    x,
-                    y,
-                    color: (255, 255, 255),
-                })
+// pixels of a progressive rendering in the order of the passes,
+// while worker threads are still computing the later ones
+pub struct Progressive {
+    rx: mpsc::Receiver<(usize, Vec<Pixel>)>,
+    // jobs that finished before the ones in front of them
+    pending: BTreeMap<usize, Vec<Pixel>>,
+    next: usize,
+}
+
+impl Iterator for Progressive {
+    type Item = Vec<Pixel>;
+
+    fn next(&mut self) -> Option<Vec<Pixel>> {
+        loop {
+            if let Some(pixels) = self.pending.remove(&self.next) {
+                self.next += 1;
+                return Some(pixels);
+            }
+            let (i, pixels) = self.rx.recv().ok()?;
+            self.pending.insert(i, pixels);
+        }
+    }
+}
+
+impl Fractal {
+    fn render_band(&self, rect: Rect, scale: &Scale, pass: &Pass, ys: std::ops::Range<usize>) -> Vec<Pixel> {
+        let mut pixels = Vec::new();
+        for l in &pass.lattices {
+            let first = l.y + ys.start.saturating_sub(l.y).div_ceil(l.dy) * l.dy;
+            for y in (first..ys.end).step_by(l.dy) {
+                for x in (l.x..rect.w).step_by(l.dx) {
+                    let color = self.color(scale, self.escape(self.coordinate(x, y, rect.w, rect.h)));
+                    for by in y..(y + pass.block_h).min(rect.h) {
+                        for bx in x..(x + pass.block_w).min(rect.w) {
+                            pixels.push(Pixel { x: rect.x + bx, y: rect.y + by, color });
+                        }
+                    }
+                }
+            }
+        }
+        pixels
+    }
+
+    // a coarse image comes first and is refined by the later passes, every
+    // pass is split into bands that are rendered on the given number of threads
+    pub fn progressive(&self, rect: Rect, progression: Progression, threads: usize) -> Progressive {
+        let fractal = Arc::new(self.clone());
+        // histogram coloring can't wait for all pixels, so it ranks against a small preview
+        let samples = self.escape_times(rect.w.div_ceil(COARSE_BLOCK / 2), rect.h.div_ceil(COARSE_BLOCK / 2));
+        let scale = Arc::new(self.scale(&samples[..]));
+        let jobs: Arc<Vec<(Pass, std::ops::Range<usize>)>> = Arc::new(
+            progression
+                .passes()
+                .into_iter()
+                .flat_map(|pass| (0..rect.h).step_by(BAND_HEIGHT).map(move |y| (pass.clone(), y..(y + BAND_HEIGHT).min(rect.h))))
+                .collect(),
+        );
+        let next_job = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..threads.max(1) {
+            let (fractal, scale, jobs, next_job, tx) = (fractal.clone(), scale.clone(), jobs.clone(), next_job.clone(), tx.clone());
+            std::thread::spawn(move || loop {
+                let i = next_job.fetch_add(1, Ordering::Relaxed);
+                let (pass, ys) = match jobs.get(i) {
+                    Some(job) => job,
+                    None => break,
+                };
+                // the receiver is gone, nobody wants the rest
+                if tx.send((i, fractal.render_band(rect, &scale, pass, ys.clone()))).is_err() {
+                    break;
+                }
+            });
+        }
+        Progressive { rx, pending: BTreeMap::new(), next: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn powi_matches_repeated_multiplication() {
+        let z = Complex::from(0.7, -0.4);
+        let mut expected = Complex::from(1.0, 0.0);
+        for n in 0..40 {
+            let p = z.powi(n);
+            assert!((p - expected).norm_sqr() < 1e-20, "z^{} = {:?}, expected {:?}", n, p, expected);
+            expected = expected * z;
+        }
+        assert_eq!(Complex::from(0.0, 1.0).powi(2), Complex::from(-1.0, 0.0));
+    }
+
+    #[test]
+    fn multibrot_power_is_bounded() {
+        assert!(parse_formula("multibrot").is_ok());
+        assert!(parse_formula("multibrot:2").is_ok());
+        assert!(parse_formula("multibrot:64").is_ok());
+        for invalid in ["multibrot:0", "multibrot:1", "multibrot:65", "multibrot:4000000000", "multibrot:-3", "multibrot:3:4"] {
+            assert!(parse_formula(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn smooth_escape_times_grow_with_the_iterations() {
+        for formula in [parse_formula("mandelbrot").unwrap(), parse_formula("multibrot:3").unwrap()] {
+            let fractal = Fractal::new(formula.clone());
+            let radius_sqr = fractal.escape_radius * fractal.escape_radius;
+            // |z|^2 right after escaping is between R^2 and R^(2 degree)
+            let norms: Vec<f64> = (0..=20).map(|k| radius_sqr.powf(1.0 + (formula.degree() - 1.0) * k as f64 / 20.0)).collect();
+            let mut last = 0.0;
+            for i in 1..100 {
+                // a larger |z| escaped earlier within the same iteration
+                for &norm_sqr in norms.iter().rev() {
+                    let t = fractal.escape_time(i, norm_sqr);
+                    assert!(t >= last - 1e-9, "{} after {} at iteration {}", t, last, i);
+                    assert!(t > i as f64 - 1.0 - 1e-9 && t <= i as f64 + 1e-9, "{} at iteration {}", t, i);
+                    last = t;
+                }
+            }
+        }
+
+        // and along a ray out of the set, points further out escape sooner
+        let fractal = Fractal::default();
+        let times: Vec<f64> = (0..200).map(|k| fractal.escape(Complex::from(0.26 + k as f64 * 0.01, 0.0)).unwrap()).collect();
+        assert!(times.windows(2).all(|w| w[0] >= w[1]), "{:?}", times);
+    }
+
+    #[test]
+    fn histogram_coloring_spreads_over_the_whole_gradient() {
+        let mut fractal = Fractal { iterations: 200, ..Fractal::default() };
+        let times = fractal.escape_times(96, 72);
+        let escaped = times.iter().filter(|t| t.is_some()).count();
+        let grays = |fractal: &Fractal| -> Vec<u8> {
+            let mut grays: Vec<u8> = fractal
+                .colorize(&times[..])
+                .into_iter()
+                .zip(&times)
+                .filter(|(_, t)| t.is_some())
+                .map(|(color, _)| color.0)
+                .collect();
+            grays.sort();
+            grays
+        };
+
+        // most points escape early, so a linear scale leaves them dark
+        let smooth = grays(&fractal);
+        assert!(smooth[escaped / 2] < 20, "median {}", smooth[escaped / 2]);
+
+        fractal.coloring = Coloring::Histogram;
+        let histogram = grays(&fractal);
+        assert_eq!(histogram[0], 0);
+        assert!(histogram[escaped - 1] >= 250, "brightest {}", histogram[escaped - 1]);
+        // every part of the gradient gets its share of the points
+        for q in 1..10 {
+            let gray = histogram[escaped * q / 10] as f64;
+            assert!((gray - 255.0 * q as f64 / 10.0).abs() < 26.0, "{}% at {}", q * 10, gray);
+        }
+    }
+
+    #[test]
+    fn progressive_passes_sample_every_pixel_once() {
+        for progression in [Progression::Coarse, Progression::Adam7] {
+            for (w, h) in [(1, 1), (37, 29), (64, 70)] {
+                let mut samples = vec![0; w * h];
+                for pass in progression.passes() {
+                    for l in &pass.lattices {
+                        for y in (l.y..h).step_by(l.dy) {
+                            for x in (l.x..w).step_by(l.dx) {
+                                samples[y * w + x] += 1;
+                            }
+                        }
+                    }
+                }
+                assert!(samples.iter().all(|&n| n == 1), "{:?} {}x{}: {:?}", progression, w, h, samples);
+            }
+        }
+    }
+
+    #[test]
+    fn progressive_rendering_ends_with_the_full_image() {
+        let rect = Rect { x: 5, y: 3, w: 45, h: 70 };
+        for coloring in [Coloring::Plain, Coloring::Smooth] {
+            let fractal = Fractal { iterations: 64, coloring, ..Fractal::default() };
+            let expected = fractal.render(rect);
+            for progression in [Progression::Coarse, Progression::Adam7] {
+                for threads in [1, 3] {
+                    let mut image = vec![None; rect.w * rect.h];
+                    for pixels in fractal.progressive(rect, progression, threads) {
+                        for px in pixels {
+                            assert!(px.x >= rect.x && px.x < rect.x + rect.w && px.y >= rect.y && px.y < rect.y + rect.h, "{:?}", px);
+                            image[(px.y - rect.y) * rect.w + px.x - rect.x] = Some(px.color);
+                        }
+                    }
+                    let colors: Vec<_> = expected.iter().map(|px| Some(px.color)).collect();
+                    assert!(image == colors, "{:?} {:?} on {} threads", coloring, progression, threads);
+                }
             }
         }
     }
-    v
 }
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::primitive::Pixel;
+use crate::protocol::{Protocol, ServerInfo};
+use crate::session::Session;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sharding {
+    // pixel i goes to connection i % n, keeps the drawing order visible on the wall
+    RoundRobin,
+    // connection i gets the i-th vertical stripe of the screen
+    Region,
+}
+
+impl std::str::FromStr for Sharding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Sharding::RoundRobin),
+            "region" => Ok(Sharding::Region),
+            _ => Err(format!("unknown sharding {:?}, expected round-robin or region", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PoolStats {
+    pub pixels: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl PoolStats {
+    pub fn pixels_per_second(&self) -> f64 {
+        self.pixels as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} pixels ({} bytes) in {:.2}s, {:.0} pixels/s",
+            self.pixels, self.bytes, self.elapsed.as_secs_f64(), self.pixels_per_second())
+    }
+}
+
+// connections next to the one a session already has, the session draws a shard
+// as well. every connection is a session of its own, so it reconnects with
+// backoff and continues its shard where it was interrupted
+pub struct ConnectionPool<S: Read + Write, P: Protocol + Clone> {
+    sessions: Vec<Session<S, P>>,
+    // bytes, as for Session::write_pixels
+    batch_size: usize,
+}
+
+impl<S: Read + Write + Send, P: Protocol + Clone + Send> ConnectionPool<S, P> {
+    // extra connections to the server of session
+    pub fn connect(session: &Session<S, P>, extra: usize, batch_size: usize) -> Result<Self> {
+        let sessions = (0..extra).map(|_| session.sibling()).collect::<Result<Vec<_>>>()?;
+        Ok(Self::from_sessions(sessions, batch_size))
+    }
+
+    pub fn from_sessions(sessions: Vec<Session<S, P>>, batch_size: usize) -> Self {
+        Self { sessions, batch_size }
+    }
+
+    // extra connections, not counting the session that draws with them
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    // one shard for the session and one for every extra connection
+    pub fn shard(&self, pixels: &[Pixel], sharding: Sharding, info: ServerInfo) -> Vec<Vec<Pixel>> {
+        let n = self.sessions.len() + 1;
+        let mut shards: Vec<Vec<Pixel>> = vec![Vec::with_capacity(pixels.len() / n + 1); n];
+        match sharding {
+            Sharding::RoundRobin => {
+                for (i, px) in pixels.iter().enumerate() {
+                    shards[i % n].push(*px);
+                }
+            }
+            Sharding::Region => {
+                let stripe_width = (info.width as usize).div_ceil(n).max(1);
+                for px in pixels {
+                    shards[(px.x / stripe_width).min(n - 1)].push(*px);
+                }
+            }
+        }
+        shards
+    }
+
+    // every connection writes its shard on its own thread, the first error that
+    // reconnecting could not fix wins
+    pub fn draw(&mut self, session: &mut Session<S, P>, pixels: &[Pixel], sharding: Sharding) -> Result<PoolStats> {
+        let shards = self.shard(pixels, sharding, session.info());
+        let batch_size = self.batch_size;
+        let start = Instant::now();
+        let results: Vec<Result<(u64, u64)>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = std::iter::once(session)
+                .chain(self.sessions.iter_mut())
+                .zip(shards.iter())
+                .map(|(session, shard)| scope.spawn(move || {
+                    let pixels = session.write_pixels(shard.iter().copied(), batch_size)?;
+                    let bytes = shard.iter().map(|px| session.protocol().print_cost(px) as u64).sum();
+                    Ok((pixels, bytes))
+                }))
+                .collect();
+            handles.into_iter().map(|h| h.join().expect("pool thread panicked")).collect()
+        });
+        let mut stats = PoolStats { elapsed: start.elapsed(), ..Default::default() };
+        for result in results {
+            let (pixels, bytes) = result?;
+            stats.pixels += pixels;
+            stats.bytes += bytes;
+        }
+        Ok(stats)
+    }
+}
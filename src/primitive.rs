@@ -47,4 +47,102 @@ impl Rect {
     pub fn ys_abs(&self) -> std::ops::Range<usize> {
         self.y .. (self.y + self.h)
     }
+
+    // splits the rect into row-major tiles of at most tile_w x tile_h
+    pub fn tiles(&self, tile_w: usize, tile_h: usize) -> Vec<Rect> {
+        let mut tiles = Vec::new();
+        for y in self.ys_abs().step_by(tile_h.max(1)) {
+            for x in self.xs_abs().step_by(tile_w.max(1)) {
+                tiles.push(Rect {
+                    x,
+                    y,
+                    w: tile_w.min(self.x + self.w - x),
+                    h: tile_h.min(self.y + self.h - y),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+// RRGGBB, optionally with a leading #
+pub fn parse_color(s: &str) -> Result<(u8, u8, u8), String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid color {:?}, expected RRGGBB", s));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok((channel(0), channel(2), channel(4)))
+}
+
+// per-channel mean, rounded down
+pub fn average(colors: &[(u8, u8, u8)]) -> (u8, u8, u8) {
+    let n = colors.len().max(1) as u64;
+    let sum = colors.iter().fold((0u64, 0u64, 0u64), |s, c| (s.0 + c.0 as u64, s.1 + c.1 as u64, s.2 + c.2 as u64));
+    ((sum.0 / n) as u8, (sum.1 / n) as u8, (sum.2 / n) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("ff8000"), Ok((255, 128, 0)));
+        assert_eq!(parse_color("#0aB0c0"), Ok((10, 176, 192)));
+        for invalid in ["", "fff", "ff80000", "ff800", "gg0000", "+f0000", "ff 000", "##ff0000", "ff80\u{e9}"] {
+            assert!(parse_color(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    // every pixel of rect is in exactly one tile
+    fn assert_covers(rect: Rect, tiles: &[Rect]) {
+        let mut hits = vec![0; rect.w * rect.h];
+        for tile in tiles {
+            for y in tile.ys_abs() {
+                for x in tile.xs_abs() {
+                    hits[rect.index_abs(x, y)] += 1;
+                }
+            }
+        }
+        assert!(hits.iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn tiles_of_a_multiple() {
+        let rect = Rect { x: 2, y: 3, w: 8, h: 6 };
+        let tiles = rect.tiles(4, 3);
+        let sizes: Vec<_> = tiles.iter().map(|t| (t.x, t.y, t.w, t.h)).collect();
+        assert_eq!(sizes, [(2, 3, 4, 3), (6, 3, 4, 3), (2, 6, 4, 3), (6, 6, 4, 3)]);
+        assert_covers(rect, &tiles[..]);
+    }
+
+    #[test]
+    fn tiles_with_leftovers() {
+        let rect = Rect { x: 3, y: 4, w: 10, h: 7 };
+        let tiles = rect.tiles(4, 3);
+        assert_eq!(tiles.len(), 9);
+        let last = tiles[8];
+        assert_eq!((last.x, last.y, last.w, last.h), (11, 10, 2, 1));
+        assert_covers(rect, &tiles[..]);
+    }
+
+    #[test]
+    fn tiles_larger_than_the_wire_format() {
+        let rect = Rect { x: 0, y: 0, w: 9000, h: 2 };
+        let tiles = rect.tiles(4095, 4095);
+        let widths: Vec<_> = tiles.iter().map(|t| t.w).collect();
+        assert_eq!(widths, [4095, 4095, 810]);
+        assert_covers(rect, &tiles[..]);
+    }
+
+    #[test]
+    fn single_pixel_tiles() {
+        let rect = Rect { x: 1, y: 1, w: 3, h: 2 };
+        let tiles = rect.tiles(1, 1);
+        assert_eq!(tiles.len(), 6);
+        assert_covers(rect, &tiles[..]);
+        // a tile size of 0 is treated as 1
+        assert_eq!(rect.tiles(0, 0).len(), 6);
+    }
 }
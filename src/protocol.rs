@@ -0,0 +1,458 @@
+use std::io::{ErrorKind, Read, Write};
+
+use crate::error::{PixelflutError, Result};
+use crate::primitive::{parse_color, Pixel, Rect};
+
+#[derive(Debug, Copy, Clone)]
+pub struct ServerInfo {
+    pub width: u32,
+    pub height: u32,
+    pub recv_buffer_size: u32,
+    pub send_buffer_size: u32,
+}
+
+pub trait Protocol {
+    fn info<S: Read + Write>(&self, stream: &mut S) -> Result<ServerInfo>;
+    // appends the bytes of a single pixel write to buf, used for batching
+    fn encode_print(&self, px: &Pixel, buf: &mut Vec<u8>) -> Result<()>;
+    fn get<S: Read + Write>(&self, px: &mut Pixel, stream: &mut S) -> Result<()>;
+
+    fn print<S: Write>(&self, px: &Pixel, stream: &mut S) -> Result<()> {
+        let mut buf = Vec::new();
+        self.encode_print(px, &mut buf)?;
+        stream.write_all(&buf[..])?;
+        Ok(())
+    }
+
+    fn rectangle_get<S: Read + Write>(&self, colors: &mut [(u8, u8, u8)], rect: Rect, stream: &mut S) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        for y in rect.ys_abs() {
+            for x in rect.xs_abs() {
+                let mut px = Pixel { x, y, color: (0, 0, 0) };
+                self.get(&mut px, stream)?;
+                colors[rect.index_abs(x, y)] = px.color;
+            }
+        }
+        Ok(())
+    }
+
+    fn rectangle_print<S: Write>(&self, colors: &[(u8, u8, u8)], rect: Rect, stream: &mut S) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        for y in rect.ys_abs() {
+            for x in rect.xs_abs() {
+                self.print(&Pixel { x, y, color: colors[rect.index_abs(x, y)] }, stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rectangle_fill<S: Write>(&self, color: (u8, u8, u8), rect: Rect, stream: &mut S) -> Result<()> {
+        for y in rect.ys_abs() {
+            for x in rect.xs_abs() {
+                self.print(&Pixel { x, y, color }, stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn offset<S: Write>(&self, _x: usize, _y: usize, _stream: &mut S) -> Result<()> {
+        Err(PixelflutError::Unsupported("OFFSET"))
+    }
+
+    // largest rectangle a single rectangle command can encode, None if unlimited
+    fn max_rect_size(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    // bytes on the wire for a single pixel write
+    fn print_cost(&self, px: &Pixel) -> usize {
+        let mut buf = Vec::new();
+        self.encode_print(px, &mut buf).map(|_| buf.len()).unwrap_or(0)
+    }
+
+    // largest part of rect that one rectangle command can carry when the server
+    // reads or writes buffer_size bytes at once, binary rectangle commands
+    // transfer 4 bytes per pixel
+    fn tile_size(&self, rect: Rect, buffer_size: u32, header_size: usize) -> (usize, usize) {
+        let (max_w, max_h) = self.max_rect_size().unwrap_or((usize::MAX, usize::MAX));
+        let mut tile_w = rect.w.min(max_w);
+        let mut tile_h = rect.h.min(max_h);
+        // a buffer size of 0 means the server did not tell us
+        if buffer_size > 0 {
+            let max_pixels = ((buffer_size as usize).saturating_sub(header_size) / 4).max(1);
+            tile_w = tile_w.min(max_pixels);
+            tile_h = tile_h.min(max_pixels / tile_w.max(1));
+        }
+        (tile_w, tile_h)
+    }
+
+    // bytes on the wire for printing the whole rectangle to a server with
+    // the given receive buffer size
+    fn rectangle_print_cost(&self, rect: Rect, _buffer_size: u32) -> usize {
+        rect.ys_abs()
+            .flat_map(|y| rect.xs_abs().map(move |x| Pixel { x, y, color: (0, 0, 0) }))
+            .map(|px| self.print_cost(&px))
+            .sum()
+    }
+}
+
+pub fn check_color_count(actual: usize, rect: Rect) -> Result<()> {
+    if actual != rect.w * rect.h {
+        return Err(PixelflutError::ColorCount { expected: rect.w * rect.h, actual });
+    }
+    Ok(())
+}
+
+// like read_exact, but tells us how much we got before the connection closed
+fn read_full<S: Read>(stream: &mut S, buf: &mut [u8]) -> Result<()> {
+    let mut received = 0;
+    while received < buf.len() {
+        match stream.read(&mut buf[received..]) {
+            Ok(0) => return Err(PixelflutError::ShortRead { expected: buf.len(), received }),
+            Ok(n) => received += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+fn decode_u32(data: &[u8]) -> u32 {
+    (data[0] as u32)
+        | ((data[1] as u32) << 8)
+        | ((data[2] as u32) << 16)
+        | ((data[3] as u32) << 24)
+}
+
+const MAX_COORD: usize = 0xffff;
+const MAX_RECT_SIZE: usize = 0xfff;
+// bytes before the pixel data of a binary p command
+pub const RECT_HEADER_SIZE: usize = 8;
+
+fn encode_coords(x: usize, y: usize, data: &mut [u8]) -> Result<()> {
+    if x > MAX_COORD || y > MAX_COORD {
+        return Err(PixelflutError::OutOfRange { x, y });
+    }
+    // skip first byte
+    data[1] = x as u8;
+    data[2] = (x >> 8) as u8;
+    data[3] = y as u8;
+    data[4] = (y >> 8) as u8;
+    Ok(())
+}
+
+fn encode_rect(rect: Rect, data: &mut [u8]) -> Result<()> {
+    if rect.w > MAX_RECT_SIZE || rect.h > MAX_RECT_SIZE {
+        return Err(PixelflutError::RectTooLarge(rect));
+    }
+    encode_coords(rect.x, rect.y, data)?;
+    data[5] = rect.w as u8;
+    data[6] = rect.h as u8;
+    data[7] = ((rect.w >> 8) & 0x0f) as u8 | ((rect.h >> 4) & 0xf0) as u8;
+    Ok(())
+}
+
+// 8-byte commands: I, P, G, p, g, f
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BinaryProtocol;
+
+impl Protocol for BinaryProtocol {
+    fn info<S: Read + Write>(&self, stream: &mut S) -> Result<ServerInfo> {
+        let mut data = [0u8; 8];
+        data[0] = b'I';
+        stream.write_all(&data[..])?;
+        let mut response = [0u8; 16];
+        read_full(stream, &mut response[..])?;
+        let width = decode_u32(&response[0..4]);
+        let height = decode_u32(&response[4..8]);
+        let recv_buffer_size = decode_u32(&response[8..12]);
+        let send_buffer_size = decode_u32(&response[12..16]);
+        if width == 0 || height == 0 || width as usize > MAX_COORD + 1 || height as usize > MAX_COORD + 1 {
+            return Err(PixelflutError::MalformedInfo(format!("screen size {}x{}", width, height)));
+        }
+        Ok(ServerInfo { width, height, recv_buffer_size, send_buffer_size })
+    }
+
+    fn encode_print(&self, px: &Pixel, buf: &mut Vec<u8>) -> Result<()> {
+        let mut data = [0u8; 8];
+        data[0] = b'P';
+        encode_coords(px.x, px.y, &mut data[..])?;
+        data[5] = px.color.0;
+        data[6] = px.color.1;
+        data[7] = px.color.2;
+        buf.extend_from_slice(&data[..]);
+        Ok(())
+    }
+
+    fn get<S: Read + Write>(&self, px: &mut Pixel, stream: &mut S) -> Result<()> {
+        let mut data = [0u8; 8];
+        data[0] = b'G';
+        encode_coords(px.x, px.y, &mut data[..])?;
+        stream.write_all(&data[..])?;
+        let mut recv = [0u8; 4];
+        read_full(stream, &mut recv[..])?;
+        px.color = (recv[0], recv[1], recv[2]);
+        Ok(())
+    }
+
+    fn rectangle_get<S: Read + Write>(&self, colors: &mut [(u8, u8, u8)], rect: Rect, stream: &mut S) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        let mut command: [u8; 8] = [0; 8];
+        command[0] = b'g';
+        encode_rect(rect, &mut command[..])?;
+        stream.write_all(&command[..])?;
+        // receive pixels
+        let mut data: Box<[u8; 1024]> = Box::new([0; 1024]);
+        let mut num_bytes_to_read: usize = rect.w * rect.h * 4;
+        let mut pixel_idx = 0;
+        while num_bytes_to_read > 0 {
+            let mut read_size = num_bytes_to_read;
+            if read_size > 1024 {
+                read_size = 1024;
+            }
+            read_full(stream, &mut data[0..read_size])?;
+            num_bytes_to_read -= read_size;
+            for i in (0..read_size).step_by(4) {
+                colors[pixel_idx] = (data[i], data[i + 1], data[i + 2]);
+                pixel_idx += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn rectangle_print<S: Write>(&self, colors: &[(u8, u8, u8)], rect: Rect, stream: &mut S) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        let mut data: Box<[u8; 1024]> = Box::new([0; 1024]);
+        // first round: write actual command
+        data[0] = b'p';
+        encode_rect(rect, &mut data[0..8])?;
+        let mut data_fill_start: usize = 8;
+        let mut pixel_idx = 0;
+        while pixel_idx < colors.len() {
+            // fill buffer
+            while data_fill_start <= 1024 - 4 && pixel_idx < colors.len() {
+                let col = colors[pixel_idx];
+                data[data_fill_start] = col.0;
+                data[data_fill_start + 1] = col.1;
+                data[data_fill_start + 2] = col.2;
+                data[data_fill_start + 3] = 0;
+                pixel_idx += 1;
+                data_fill_start += 4;
+            }
+            stream.write_all(&data[0..data_fill_start])?; // buffer may not be full in last round
+            data_fill_start = 0; // reset buffer
+        }
+        Ok(())
+    }
+
+    fn rectangle_fill<S: Write>(&self, color: (u8, u8, u8), rect: Rect, stream: &mut S) -> Result<()> {
+        let mut data = [0u8; 12];
+        // first round: write actual command
+        data[0] = b'f';
+        encode_rect(rect, &mut data[0..8])?;
+        data[8] = color.0;
+        data[9] = color.1;
+        data[10] = color.2;
+        stream.write_all(&data[..])?;
+        Ok(())
+    }
+
+    fn max_rect_size(&self) -> Option<(usize, usize)> {
+        Some((MAX_RECT_SIZE, MAX_RECT_SIZE))
+    }
+
+    fn print_cost(&self, _px: &Pixel) -> usize {
+        8
+    }
+
+    // one header per tile the client splits the rectangle into plus 4 bytes per pixel
+    fn rectangle_print_cost(&self, rect: Rect, buffer_size: u32) -> usize {
+        let (tile_w, tile_h) = self.tile_size(rect, buffer_size, RECT_HEADER_SIZE);
+        // an empty rectangle still goes out as one command
+        let tiles = (rect.w.div_ceil(tile_w.max(1)) * rect.h.div_ceil(tile_h.max(1))).max(1);
+        tiles * RECT_HEADER_SIZE + rect.w * rect.h * 4
+    }
+}
+
+// classic newline-terminated text protocol: SIZE, PX x y [rrggbb], OFFSET x y
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TextProtocol;
+
+fn read_line<S: Read>(stream: &mut S) -> Result<String> {
+    // byte by byte, so we never consume anything past the newline
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        read_full(stream, &mut byte[..])?;
+        if byte[0] == b'\n' {
+            break;
+        }
+        line.push(byte[0]);
+    }
+    String::from_utf8(line)
+        .map_err(|e| PixelflutError::MalformedResponse(String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+fn parse_hex_color(s: &str) -> Option<(u8, u8, u8)> {
+    // servers may answer with rrggbb or rrggbbaa
+    match s.len() {
+        6 => parse_color(s).ok(),
+        8 if s.is_ascii() && s[6..].bytes().all(|b| b.is_ascii_hexdigit()) => parse_color(&s[..6]).ok(),
+        _ => None,
+    }
+}
+
+impl TextProtocol {
+    fn parse_px_response(&self, line: &str, x: usize, y: usize) -> Result<(u8, u8, u8)> {
+        let invalid = || PixelflutError::MalformedResponse(line.to_string());
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 4 || parts[0] != "PX" {
+            return Err(invalid());
+        }
+        if parts[1].parse::<usize>().ok() != Some(x) || parts[2].parse::<usize>().ok() != Some(y) {
+            return Err(invalid());
+        }
+        parse_hex_color(parts[3]).ok_or_else(invalid)
+    }
+}
+
+impl Protocol for TextProtocol {
+    fn info<S: Read + Write>(&self, stream: &mut S) -> Result<ServerInfo> {
+        stream.write_all(b"SIZE\n")?;
+        let line = read_line(stream)?;
+        let invalid = || PixelflutError::MalformedInfo(line.clone());
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 3 || parts[0] != "SIZE" {
+            return Err(invalid());
+        }
+        let width: u32 = parts[1].parse().map_err(|_| invalid())?;
+        let height: u32 = parts[2].parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        // the text protocol does not tell us about buffer sizes
+        Ok(ServerInfo { width, height, recv_buffer_size: 0, send_buffer_size: 0 })
+    }
+
+    fn encode_print(&self, px: &Pixel, buf: &mut Vec<u8>) -> Result<()> {
+        writeln!(buf, "PX {} {} {:02x}{:02x}{:02x}", px.x, px.y, px.color.0, px.color.1, px.color.2)?;
+        Ok(())
+    }
+
+    fn get<S: Read + Write>(&self, px: &mut Pixel, stream: &mut S) -> Result<()> {
+        let s = format!("PX {} {}\n", px.x, px.y);
+        stream.write_all(s.as_bytes())?;
+        let line = read_line(stream)?;
+        px.color = self.parse_px_response(&line, px.x, px.y)?;
+        Ok(())
+    }
+
+    fn rectangle_get<S: Read + Write>(&self, colors: &mut [(u8, u8, u8)], rect: Rect, stream: &mut S) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        // pipeline one row of requests at a time instead of waiting for every single response
+        for y in rect.ys_abs() {
+            let mut requests = String::new();
+            for x in rect.xs_abs() {
+                requests.push_str(&format!("PX {} {}\n", x, y));
+            }
+            stream.write_all(requests.as_bytes())?;
+            for x in rect.xs_abs() {
+                let line = read_line(stream)?;
+                colors[rect.index_abs(x, y)] = self.parse_px_response(&line, x, y)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rectangle_print<S: Write>(&self, colors: &[(u8, u8, u8)], rect: Rect, stream: &mut S) -> Result<()> {
+        check_color_count(colors.len(), rect)?;
+        for y in rect.ys_abs() {
+            let mut commands = Vec::new();
+            for x in rect.xs_abs() {
+                self.encode_print(&Pixel { x, y, color: colors[rect.index_abs(x, y)] }, &mut commands)?;
+            }
+            stream.write_all(&commands[..])?;
+        }
+        Ok(())
+    }
+
+    fn rectangle_fill<S: Write>(&self, color: (u8, u8, u8), rect: Rect, stream: &mut S) -> Result<()> {
+        self.rectangle_print(&vec![color; rect.w * rect.h][..], rect, stream)
+    }
+
+    fn offset<S: Write>(&self, x: usize, y: usize, stream: &mut S) -> Result<()> {
+        let s = format!("OFFSET {} {}\n", x, y);
+        stream.write_all(s.as_bytes())?;
+        Ok(())
+    }
+}
+
+// selects a protocol at runtime, e.g. from the command line or a config file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProtocolKind {
+    Binary,
+    Text,
+}
+
+impl std::str::FromStr for ProtocolKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(ProtocolKind::Binary),
+            "text" => Ok(ProtocolKind::Text),
+            _ => Err(format!("unknown protocol {:?}, expected binary or text", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(w: usize, h: usize) -> Rect {
+        Rect { x: 0, y: 0, w, h }
+    }
+
+    #[test]
+    fn tiles_are_limited_by_the_wire_format() {
+        assert_eq!(BinaryProtocol.tile_size(rect(5000, 10), 0, 0), (4095, 10));
+        assert_eq!(BinaryProtocol.tile_size(rect(5000, 5000), 0, 0), (4095, 4095));
+        assert_eq!(BinaryProtocol.tile_size(rect(4095, 4095), 0, 0), (4095, 4095));
+        // the text protocol has no rectangle commands to limit
+        assert_eq!(TextProtocol.tile_size(rect(5000, 5000), 0, 0), (5000, 5000));
+    }
+
+    #[test]
+    fn tiles_are_limited_by_the_buffer_size() {
+        // 254 pixels fit into 1024 bytes after the header
+        assert_eq!(BinaryProtocol.tile_size(rect(100, 100), 1024, RECT_HEADER_SIZE), (100, 2));
+        assert_eq!(BinaryProtocol.tile_size(rect(300, 100), 1024, RECT_HEADER_SIZE), (254, 1));
+        assert_eq!(BinaryProtocol.tile_size(rect(30, 7), 408, RECT_HEADER_SIZE), (30, 3));
+        // a whole rect that fits stays whole
+        assert_eq!(BinaryProtocol.tile_size(rect(10, 10), 1024, 0), (10, 10));
+    }
+
+    #[test]
+    fn tiny_buffers_still_send_a_pixel_at_a_time() {
+        assert_eq!(BinaryProtocol.tile_size(rect(20, 20), 4, RECT_HEADER_SIZE), (1, 1));
+        assert_eq!(BinaryProtocol.tile_size(rect(20, 20), 1, 0), (1, 1));
+    }
+
+    #[test]
+    fn rectangle_print_cost_counts_every_tile_header() {
+        assert_eq!(BinaryProtocol.rectangle_print_cost(rect(10, 10), 0), 8 + 400);
+        assert_eq!(BinaryProtocol.rectangle_print_cost(rect(5000, 1), 0), 2 * 8 + 20000);
+        // 50 tiles of 100x2
+        assert_eq!(BinaryProtocol.rectangle_print_cost(rect(100, 100), 1024), 50 * 8 + 40000);
+        // one pixel per command
+        assert_eq!(BinaryProtocol.rectangle_print_cost(rect(3, 2), 4), 6 * 12);
+        assert_eq!(BinaryProtocol.rectangle_print_cost(rect(0, 5), 1024), 8);
+    }
+
+    #[test]
+    fn text_rectangle_print_cost_is_the_pixel_writes() {
+        let r = Rect { x: 9, y: 10, w: 2, h: 1 };
+        assert_eq!(TextProtocol.rectangle_print_cost(r, 1024), "PX 9 10 000000\n".len() + "PX 10 10 000000\n".len());
+    }
+}
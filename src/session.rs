@@ -0,0 +1,339 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::client::PixelflutClient;
+use crate::error::Result;
+use crate::primitive::Pixel;
+use crate::protocol::{Protocol, ServerInfo};
+
+// a server that stops reading would otherwise block a write forever
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Copy, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    // None retries forever
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    // doubles with every failed attempt, capped at max
+    pub fn delay(&self, attempt: usize) -> Duration {
+        self.initial.saturating_mul(1 << attempt.min(16)).min(self.max)
+    }
+}
+
+// paces a loop to a number of steps per second
+pub struct Pacer {
+    // None doesn't wait at all
+    period: Option<Duration>,
+    next: Instant,
+}
+
+impl Pacer {
+    pub fn new(rate: Option<f64>) -> Self {
+        Self::starting_at(rate, Instant::now())
+    }
+
+    fn starting_at(rate: Option<f64>, start: Instant) -> Self {
+        Self {
+            period: rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            next: start,
+        }
+    }
+
+    // sleeps until the next step is due
+    pub fn wait(&mut self) {
+        if let Some(delay) = self.delay(Instant::now()) {
+            std::thread::sleep(delay);
+        }
+    }
+
+    // how long to sleep at now for the next step
+    fn delay(&mut self, now: Instant) -> Option<Duration> {
+        let period = self.period?;
+        self.next += period;
+        if self.next > now {
+            Some(self.next - now)
+        } else {
+            // don't rush to catch up after a slow step
+            self.next = now;
+            None
+        }
+    }
+}
+
+type Connect<S> = Arc<dyn Fn() -> std::io::Result<S> + Send + Sync>;
+
+fn connect_with_backoff<S: Read + Write, P: Protocol + Clone>(connect: &Connect<S>, protocol: &P, backoff: &Backoff) -> Result<PixelflutClient<S, P>> {
+    let mut attempt = 0;
+    loop {
+        let result = connect()
+            .map_err(Into::into)
+            .and_then(|stream| PixelflutClient::with_protocol(stream, protocol.clone()));
+        match result {
+            Err(e) if e.is_connection_error() && backoff.max_attempts.is_none_or(|n| attempt + 1 < n) => {
+                std::thread::sleep(backoff.delay(attempt));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// a client that is reconnected whenever the connection breaks, the server info
+// is queried again on every new connection
+pub struct Session<S: Read + Write, P: Protocol + Clone> {
+    connect: Connect<S>,
+    protocol: P,
+    backoff: Backoff,
+    client: Option<PixelflutClient<S, P>>,
+    info: ServerInfo,
+    reconnects: u64,
+}
+
+impl<P: Protocol + Clone> Session<TcpStream, P> {
+    pub fn tcp(host: &str, port: u16, protocol: P, backoff: Backoff) -> Result<Self> {
+        let host = host.to_string();
+        Self::new(move || {
+            let stream = TcpStream::connect((host.as_str(), port))?;
+            stream.set_read_timeout(Some(IO_TIMEOUT))?;
+            stream.set_write_timeout(Some(IO_TIMEOUT))?;
+            Ok(stream)
+        }, protocol, backoff)
+    }
+}
+
+impl<S: Read + Write, P: Protocol + Clone> Session<S, P> {
+    pub fn new<F: Fn() -> std::io::Result<S> + Send + Sync + 'static>(connect: F, protocol: P, backoff: Backoff) -> Result<Self> {
+        Self::with_connect(Arc::new(connect), protocol, backoff)
+    }
+
+    fn with_connect(connect: Connect<S>, protocol: P, backoff: Backoff) -> Result<Self> {
+        let client = connect_with_backoff(&connect, &protocol, &backoff)?;
+        Ok(Self {
+            connect,
+            protocol,
+            backoff,
+            info: client.info(),
+            client: Some(client),
+            reconnects: 0,
+        })
+    }
+
+    // info of the most recent connection
+    pub fn info(&self) -> ServerInfo {
+        self.info
+    }
+
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    // another session to the same server, with a connection of its own
+    pub fn sibling(&self) -> Result<Self> {
+        Self::with_connect(self.connect.clone(), self.protocol.clone(), self.backoff)
+    }
+
+    pub fn client(&mut self) -> Result<&mut PixelflutClient<S, P>> {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => {
+                let client = connect_with_backoff(&self.connect, &self.protocol, &self.backoff)?;
+                self.info = client.info();
+                self.reconnects += 1;
+                client
+            }
+        };
+        Ok(self.client.insert(client))
+    }
+
+    // runs f until it succeeds or fails with something other than a broken connection,
+    // f has to be safe to repeat after it was interrupted. a server that accepts
+    // and then drops us again gets the same backoff as one that refuses to connect
+    pub fn run<T, F: FnMut(&mut PixelflutClient<S, P>) -> Result<T>>(&mut self, mut f: F) -> Result<T> {
+        let mut failures = 0;
+        loop {
+            match f(self.client()?) {
+                Err(e) if e.is_connection_error() => {
+                    self.client = None;
+                    if self.backoff.max_attempts.is_some_and(|n| failures + 1 >= n) {
+                        return Err(e);
+                    }
+                    std::thread::sleep(self.backoff.delay(failures));
+                    failures += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // sends the pixels in chunks of batch_size bytes, the size of one writer buffer.
+    // the chunk that was interrupted is sent again after reconnecting
+    pub fn write_pixels<I: IntoIterator<Item = Pixel>>(&mut self, pixels: I, batch_size: usize) -> Result<u64> {
+        let mut pixels = pixels.into_iter();
+        let mut chunk = Vec::new();
+        let mut written = 0;
+        loop {
+            chunk.clear();
+            let mut bytes = 0;
+            while bytes < batch_size.max(1) {
+                match pixels.next() {
+                    Some(px) => {
+                        bytes += self.protocol.print_cost(&px);
+                        chunk.push(px);
+                    }
+                    None => break,
+                }
+            }
+            if chunk.is_empty() {
+                return Ok(written);
+            }
+            self.run(|client| {
+                let mut writer = client.writer(batch_size);
+                writer.write_pixels(&chunk[..])?;
+                writer.flush()
+            })?;
+            written += chunk.len() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // answers the info request and then resets on every write
+    struct Resetting {
+        info: std::io::Cursor<Vec<u8>>,
+        writes: usize,
+    }
+
+    impl Read for Resetting {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.info.read(buf)
+        }
+    }
+
+    impl Write for Resetting {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            if self.writes > 1 {
+                return Err(ErrorKind::ConnectionReset.into());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn resetting() -> Resetting {
+        let mut info = Vec::new();
+        for n in [64u32, 48, 1024, 1024] {
+            info.extend_from_slice(&n.to_le_bytes());
+        }
+        Resetting { info: std::io::Cursor::new(info), writes: 0 }
+    }
+
+    #[test]
+    fn run_backs_off_when_the_connection_keeps_breaking() {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counter = connects.clone();
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+            max_attempts: Some(3),
+        };
+        let mut session = Session::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(resetting())
+        }, crate::protocol::BinaryProtocol, backoff).unwrap();
+
+        let start = Instant::now();
+        let mut calls = 0;
+        let result = session.run(|client| {
+            calls += 1;
+            client.print(&Pixel { x: 1, y: 2, color: (3, 4, 5) })
+        });
+        assert!(result.unwrap_err().is_connection_error());
+        assert_eq!(calls, 3);
+        assert_eq!(connects.load(Ordering::SeqCst), 3);
+        assert_eq!(session.reconnects(), 2);
+        // 10ms and 20ms between the three attempts
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn pacer_keeps_the_rate() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut pacer = Pacer::starting_at(Some(10.0), start);
+        // a fast step sleeps for the rest of the period
+        assert_eq!(pacer.delay(start + ms(30)), Some(ms(70)));
+        // steps are due at fixed times, not a period after the last one ended
+        assert_eq!(pacer.delay(start + ms(110)), Some(ms(90)));
+        // a slow step doesn't sleep, and the next one gets a full period again
+        assert_eq!(pacer.delay(start + ms(450)), None);
+        assert_eq!(pacer.delay(start + ms(460)), Some(ms(90)));
+
+        let mut unpaced = Pacer::starting_at(None, start);
+        assert_eq!(unpaced.delay(start), None);
+    }
+
+    // answers the info request and remembers the size of every write
+    struct Recording {
+        info: std::io::Cursor<Vec<u8>>,
+        writes: Arc<std::sync::Mutex<Vec<usize>>>,
+    }
+
+    impl Read for Recording {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.info.read(buf)
+        }
+    }
+
+    impl Write for Recording {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes.lock().unwrap().push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_pixels_sends_batch_size_bytes_at_a_time() {
+        let writes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = writes.clone();
+        let mut session = Session::new(move || {
+            Ok(Recording { info: resetting().info, writes: recorded.clone() })
+        }, crate::protocol::BinaryProtocol, Backoff::default()).unwrap();
+        let pixels = (0..5).map(|x| Pixel { x, y: 0, color: (1, 2, 3) });
+        assert_eq!(session.write_pixels(pixels, 16).unwrap(), 5);
+        // the info request, then two 8 byte pixel commands per write
+        assert_eq!(*writes.lock().unwrap(), [8, 16, 16, 8]);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use crate::canvas::Canvas;
+use crate::error::Result;
+use crate::firework::{Firework, GRAVITY};
+use crate::primitive::Rect;
+use crate::protocol::Protocol;
+use crate::session::{Pacer, Session};
+
+// positions a rocket leaves glowing behind it
+const TRAIL: usize = 12;
+
+const SPARK: (u8, u8, u8) = (255, 200, 120);
+
+// a rocket on its way up, it bursts into a firework at the top of its flight
+pub struct Rocket {
+    pub x: f64,
+    pub y: f64,
+    pub vx: f64,
+    pub vy: f64,
+    pub color: (u8, u8, u8),
+    pub fast_falloff: (bool, bool, bool),
+    trail: VecDeque<(f64, f64)>,
+}
+
+impl Rocket {
+    // starts at (x, y) just fast enough to turn around at height apex
+    pub fn launch(x: f64, y: f64, apex: f64, vx: f64, color: (u8, u8, u8), fast_falloff: (bool, bool, bool)) -> Self {
+        let vy = -(2.0 * GRAVITY * (y - apex).max(0.0)).sqrt();
+        Self { x, y, vx, vy, color, fast_falloff, trail: VecDeque::with_capacity(TRAIL + 1) }
+    }
+
+    pub fn step(&mut self) {
+        self.trail.push_front((self.x, self.y));
+        self.trail.truncate(TRAIL);
+        self.x += self.vx;
+        self.y += self.vy;
+        self.vy += GRAVITY;
+    }
+
+    pub fn at_apex(&self) -> bool {
+        self.vy >= 0.0
+    }
+
+    pub fn burst(&self) -> Firework {
+        Firework::new(self.x, self.y, self.color, self.fast_falloff)
+    }
+
+    // the head and the trail behind it, fading out
+    pub fn points(&self) -> impl Iterator<Item = ((f64, f64), (u8, u8, u8))> + '_ {
+        let fade = |c: u8, i: usize| (c as usize * (TRAIL - i) / (TRAIL + 1)) as u8;
+        std::iter::once(((self.x, self.y), SPARK)).chain(
+            self.trail
+                .iter()
+                .enumerate()
+                .map(move |(i, &p)| (p, (fade(SPARK.0, i), fade(SPARK.1, i), fade(SPARK.2, i)))),
+        )
+    }
+}
+
+// many rockets and fireworks at once in a rectangle of the screen, drawn on top
+// of a snapshot of what was there before, so old particle positions get their
+// background back
+pub struct Show {
+    // frames per second
+    pub fps: f64,
+    // average launches per second
+    pub launch_rate: f64,
+    canvas: Canvas,
+    background: Vec<(u8, u8, u8)>,
+    rockets: Vec<Rocket>,
+    fireworks: Vec<Firework>,
+    // frames until the next launch
+    countdown: usize,
+    reconnects: u64,
+}
+
+impl Show {
+    pub fn new(rect: Rect) -> Self {
+        Self {
+            fps: 30.0,
+            launch_rate: 2.0,
+            canvas: Canvas::new(rect),
+            background: vec![(0, 0, 0); rect.w * rect.h],
+            rockets: Vec::new(),
+            fireworks: Vec::new(),
+            countdown: 0,
+            reconnects: 0,
+        }
+    }
+
+    pub fn with_fps(mut self, fps: f64) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    pub fn with_launch_rate(mut self, launch_rate: f64) -> Self {
+        self.launch_rate = launch_rate;
+        self
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.canvas.rect()
+    }
+
+    // nothing in the air
+    pub fn is_idle(&self) -> bool {
+        self.rockets.is_empty() && self.fireworks.is_empty()
+    }
+
+    // takes the snapshot of the background
+    pub fn fetch<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>) -> Result<()> {
+        session.run(|client| self.canvas.fetch(client))?;
+        self.background = self.canvas.screen().colors.clone();
+        self.reconnects = session.reconnects();
+        Ok(())
+    }
+
+    // a rocket from the bottom, bursting somewhere in the upper half
+    pub fn launch(&mut self) {
+        let rect = self.rect();
+        let (w, h) = (rect.w as f64, rect.h as f64);
+        let x = w * (0.1 + fastrand::f64() * 0.8);
+        let apex = h * (0.1 + fastrand::f64() * 0.4);
+        let vx = (fastrand::f64() - 0.5) * 0.6;
+        let color = (fastrand::u8(128..), fastrand::u8(128..), fastrand::u8(128..));
+        let fast_falloff = (fastrand::bool(), fastrand::bool(), fastrand::bool());
+        self.rockets.push(Rocket::launch(x, h - 1.0, apex, vx, color, fast_falloff));
+    }
+
+    // exponentially distributed waiting time, so launches come at random but
+    // at launch_rate on average
+    fn next_countdown(&self) -> usize {
+        let seconds = -(1.0 - fastrand::f64()).ln() / self.launch_rate;
+        (seconds * self.fps).round() as usize
+    }
+
+    // one frame: a launch when it is due and more are wanted, then everything moves.
+    // true if a rocket was launched
+    fn tick(&mut self, more: bool) -> bool {
+        let launch = more && self.countdown == 0;
+        if launch {
+            self.launch();
+            self.countdown = self.next_countdown();
+        } else {
+            self.countdown = self.countdown.saturating_sub(1);
+        }
+        self.step();
+        launch
+    }
+
+    pub fn step(&mut self) {
+        for rocket in &mut self.rockets {
+            rocket.step();
+        }
+        let (bursting, rising): (Vec<Rocket>, Vec<Rocket>) = self.rockets.drain(..).partition(Rocket::at_apex);
+        self.rockets = rising;
+        self.fireworks.extend(bursting.iter().map(Rocket::burst));
+        for firework in &mut self.fireworks {
+            firework.step();
+        }
+        let h = self.rect().h;
+        self.fireworks.retain(|f| !f.is_done(h));
+    }
+
+    fn draw(&mut self) {
+        let rect = self.rect();
+        let screen = self.canvas.screen_mut();
+        screen.colors.copy_from_slice(&self.background[..]);
+        // light adds up, so fading particles fade into the background
+        let mut add = |x: usize, y: usize, (r, g, b): (u8, u8, u8)| {
+            let c = &mut screen.colors[y * rect.w + x];
+            *c = (c.0.saturating_add(r), c.1.saturating_add(g), c.2.saturating_add(b));
+        };
+        for rocket in &self.rockets {
+            for ((x, y), color) in rocket.points() {
+                if x >= 0.0 && y >= 0.0 && (x as usize) < rect.w && (y as usize) < rect.h {
+                    add(x as usize, y as usize, color);
+                }
+            }
+        }
+        for firework in &self.fireworks {
+            for px in firework.pixels(rect.w, rect.h) {
+                add(px.x, px.y, px.color);
+            }
+        }
+    }
+
+    fn upload<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>, batch_size: usize) -> Result<()> {
+        self.draw();
+        session.run(|client| self.canvas.upload(client, batch_size))?;
+        // a new connection may mean a restarted server, so send everything next time
+        if session.reconnects() != self.reconnects {
+            self.reconnects = session.reconnects();
+            self.canvas.invalidate();
+        }
+        Ok(())
+    }
+
+    // launches that many rockets, or keeps going forever, and returns once the
+    // last firework has faded and the background is restored
+    pub fn run<S: Read + Write, P: Protocol + Clone>(&mut self, session: &mut Session<S, P>, launches: Option<usize>, batch_size: usize) -> Result<()> {
+        self.fetch(session)?;
+        let mut pacer = Pacer::new(Some(self.fps));
+        let mut launched = 0;
+        loop {
+            let more = launches.is_none_or(|n| launched < n);
+            if self.tick(more) {
+                launched += 1;
+            }
+            self.upload(session, batch_size)?;
+            if !more && self.is_idle() {
+                break;
+            }
+            pacer.wait();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a show on a striped background
+    fn show(w: usize, h: usize) -> Show {
+        let mut show = Show::new(Rect { x: 0, y: 0, w, h });
+        show.background = (0..w * h).map(|i| ((i % w * 5) as u8, 20, (i / w * 7) as u8)).collect();
+        show
+    }
+
+    #[test]
+    fn rockets_burst_at_their_apex() {
+        let mut rocket = Rocket::launch(10.0, 59.0, 20.0, 0.0, (255, 0, 0), (false, false, false));
+        let mut steps = 0;
+        while !rocket.at_apex() {
+            rocket.step();
+            steps += 1;
+            assert!(steps < 100);
+        }
+        assert!((rocket.y - 20.0).abs() < 2.0, "{}", rocket.y);
+        // the head and a full trail
+        assert_eq!(rocket.points().count(), TRAIL + 1);
+        let firework = rocket.burst();
+        assert_eq!((firework.x, firework.y), (rocket.x, rocket.y));
+    }
+
+    #[test]
+    fn fireworks_are_done_once_faded_or_below_the_screen() {
+        fastrand::seed(2);
+        assert!(Firework::new(5.0, 5.0, (0, 0, 0), (false, false, false)).is_done(50));
+        assert!(Firework::new(5.0, 100.0, (255, 255, 255), (false, false, false)).is_done(50));
+        let mut firework = Firework::new(5.0, 5.0, (255, 255, 255), (true, false, true));
+        assert!(!firework.is_done(50));
+        let mut steps = 0;
+        while !firework.is_done(50) {
+            firework.step();
+            steps += 1;
+            assert!(steps < 500);
+        }
+    }
+
+    #[test]
+    fn countdowns_follow_the_launch_rate() {
+        fastrand::seed(4);
+        let show = show(10, 10).with_fps(30.0).with_launch_rate(2.0);
+        let n = 4000;
+        let mean = (0..n).map(|_| show.next_countdown()).sum::<usize>() as f64 / n as f64;
+        // 15 frames between launches
+        assert!((14.0..16.0).contains(&mean), "{}", mean);
+    }
+
+    #[test]
+    fn finished_fireworks_leave_the_background_behind() {
+        fastrand::seed(9);
+        let (w, h) = (60, 40);
+        let mut show = show(w, h).with_fps(30.0).with_launch_rate(4.0);
+        let mut launched = 0;
+        let mut lit = false;
+        for _ in 0..150 {
+            if show.tick(launched < 5) {
+                launched += 1;
+            }
+            // done fireworks are gone in the tick they finish
+            assert!(show.fireworks.iter().all(|f| !f.is_done(h)));
+            show.draw();
+            lit |= show.canvas.screen().colors != show.background;
+        }
+        assert_eq!(launched, 5);
+        assert!(lit);
+
+        let mut ticks = 0;
+        while !show.is_idle() {
+            assert!(!show.tick(false));
+            ticks += 1;
+            assert!(ticks < 1000, "the show never ends");
+        }
+        show.draw();
+        assert_eq!(show.canvas.screen().colors, show.background);
+    }
+}
@@ -1,12 +1,11 @@
 use crate::primitive::Pixel;
 
-struct Worm {
+pub struct Worm {
     x: f64,
     y: f64,
     old_x: f64,
     old_y: f64,
     angle: f64,
-    starting_angle: f64,
     velo: f64,
     size: usize,
     color: (u8, u8, u8),
@@ -21,7 +20,6 @@ impl Worm {
             old_x: x,
             old_y: y,
             angle,
-            starting_angle: angle,
             velo,
             size,
             color,
@@ -30,7 +28,7 @@ impl Worm {
     }
 }
 
-struct WormResult {
+pub struct WormResult {
     new_worms: Vec<Worm>,
     pixels: Vec<Pixel>,
 }
@@ -46,7 +44,7 @@ pub trait TreeDraw {
     fn leaves(&self, worm: &Worm) -> Vec<Pixel>;
     fn children(&self, worm: &Worm) -> Vec<Worm>;
 
-    fn worm_step(&self, worm: &mut Worm, screen_width: usize, screen_height: usize) -> Option<WormResult> {
+    fn worm_step(&self, worm: &mut Worm, screen_width: usize, _screen_height: usize) -> Option<WormResult> {
         worm.steps += 1;
 
         let mut pixels = Vec::new();
@@ -78,7 +76,7 @@ pub trait TreeDraw {
         pixels.append(&mut dc_pixels((worm.x as usize, worm.y as usize), worm.size - 1, (0, 0, 0)));
         // draw middle little black circle
         pixels.append(&mut dc_pixels((worm.old_x as usize, worm.old_y as usize), old_size - 1, (0, 0, 0)));
-        return Some(WormResult { new_worms: self.children(worm), pixels });
+        Some(WormResult { new_worms: self.children(worm), pixels })
     }
 
     fn steps(&self, screen_width: usize, screen_height: usize) -> Vec<Vec<Pixel>> {
@@ -87,7 +85,7 @@ pub trait TreeDraw {
         let mut step_pixels: Vec<Vec<Pixel>> = Vec::new();
         let mut current_pixels: Vec<Pixel> = Vec::new();
         worms.push(self.starting_worm(screen_width, screen_height));
-        while worms.len() > 0 {
+        while !worms.is_empty() {
             for mut worm in worms.drain(..) {
                 if let Some(WormResult { mut new_worms, mut pixels }) = self.worm_step(&mut worm, screen_width, screen_height) {
                     current_pixels.append(&mut pixels);
@@ -173,10 +171,8 @@ pub fn dc(center: (usize, usize), radius: usize) -> Vec<(usize, usize)> {
     let ubx = icx + ir;
     for y in lby..=uby {
         for x in lbx..=ubx {
-            if (y - icy) * (y - icy) + (x - icx) * (x - icx) < ir * ir {
-                if x >= 0 && y >= 0 {
-                    coords.push((x as usize, y as usize));
-                }
+            if (y - icy) * (y - icy) + (x - icx) * (x - icx) < ir * ir && x >= 0 && y >= 0 {
+                coords.push((x as usize, y as usize));
             }
         }
     }
@@ -186,7 +182,7 @@ pub fn dc(center: (usize, usize), radius: usize) -> Vec<(usize, usize)> {
 pub struct DefaultTreeDraw;
 
 impl TreeDraw for DefaultTreeDraw {
-    fn delta_angle(&self, worm: &Worm) -> f64 {
+    fn delta_angle(&self, _worm: &Worm) -> f64 {
         let max_deviation = 0.5; // should be less than 2 pi
         let d = fastrand::f64() * max_deviation;
         d - max_deviation / 2.0
@@ -241,12 +237,12 @@ impl TreeDraw for DefaultTreeDraw {
 pub struct SymmetricTreeDraw;
 
 impl TreeDraw for  SymmetricTreeDraw {
-    fn delta_angle(&self, worm: &Worm) -> f64 {
+    fn delta_angle(&self, _worm: &Worm) -> f64 {
         0.0
     }
 
     fn delta_size(&self, worm: &Worm) -> isize {
-        if worm.steps % 10 == 0 {
+        if worm.steps.is_multiple_of(10) {
             -1
         } else {
             0
@@ -279,7 +275,7 @@ impl TreeDraw for  SymmetricTreeDraw {
             // create new worms
             // size is between 20 and 4
             // let additional_fac = (20 - self.size) as f64 / 100.0; // between 0.26 and 0.1
-            if worm.steps % 30 == 0 {
+            if worm.steps.is_multiple_of(30) {
                 let new_worm = Worm::from(worm.old_x, worm.old_y, worm.angle + 0.4, worm.velo, worm.size, worm.color);
                 new_worms.push(new_worm);
                 let new_worm = Worm::from(worm.old_x, worm.old_y, worm.angle - 0.4, worm.velo, worm.size, worm.color);
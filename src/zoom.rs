@@ -0,0 +1,360 @@
+use std::sync::Arc;
+
+use crate::mandel::{Complex, Fractal, Mandelbrot, View};
+
+// an unevaluated sum hi + lo with about 32 significant digits
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DoubleDouble {
+    pub hi: f64,
+    pub lo: f64,
+}
+
+// exact a + b as a rounded sum and its error
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+// same as two_sum, but only if |a| >= |b|
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    (p, a.mul_add(b, -p))
+}
+
+impl DoubleDouble {
+    pub const ZERO: Self = Self { hi: 0.0, lo: 0.0 };
+
+    pub fn from(x: f64) -> Self {
+        Self { hi: x, lo: 0.0 }
+    }
+
+    fn normalized((hi, lo): (f64, f64)) -> Self {
+        let (hi, lo) = quick_two_sum(hi, lo);
+        Self { hi, lo }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+}
+
+impl std::ops::Neg for DoubleDouble {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self { hi: -self.hi, lo: -self.lo }
+    }
+}
+
+impl std::ops::Add for DoubleDouble {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let (s, e) = two_sum(self.hi, other.hi);
+        let (t, f) = two_sum(self.lo, other.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        Self::normalized((s, e + f))
+    }
+}
+
+impl std::ops::Sub for DoubleDouble {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self + -other
+    }
+}
+
+impl std::ops::Mul for DoubleDouble {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let (p, e) = two_prod(self.hi, other.hi);
+        Self::normalized((p, e + (self.hi * other.lo + self.lo * other.hi)))
+    }
+}
+
+impl std::ops::Div for DoubleDouble {
+    type Output = Self;
+
+    // long division with three f64 quotient digits
+    fn div(self, other: Self) -> Self {
+        let q1 = self.hi / other.hi;
+        let r = self - other * Self::from(q1);
+        let q2 = r.hi / other.hi;
+        let r = r - other * Self::from(q2);
+        let q3 = r.hi / other.hi;
+        Self::normalized((q1, q2)) + Self::from(q3)
+    }
+}
+
+// decimal numbers like -0.743643887037158704752191506114774,
+// parsed digit by digit so nothing beyond f64 precision gets lost
+impl std::str::FromStr for DoubleDouble {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid number {:?}", s);
+        let t = s.trim();
+        let (negative, t) = match t.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, t.strip_prefix('+').unwrap_or(t)),
+        };
+        let (int, frac) = t.split_once('.').unwrap_or((t, ""));
+        if int.is_empty() && frac.is_empty() {
+            return Err(invalid());
+        }
+        let ten = Self::from(10.0);
+        let mut value = Self::ZERO;
+        let mut divisor = Self::from(1.0);
+        for (i, c) in int.chars().chain(frac.chars()).enumerate() {
+            let digit = c.to_digit(10).ok_or_else(invalid)?;
+            value = value * ten + Self::from(digit as f64);
+            if i >= int.len() {
+                divisor = divisor * ten;
+            }
+        }
+        let value = value / divisor;
+        Ok(if negative { -value } else { value })
+    }
+}
+
+// zooming into the mandelbrot set, the view shrinks by factor every frame
+#[derive(Debug, Clone)]
+pub struct Zoom {
+    // iterations, escape radius and colors, the formula is always mandelbrot
+    pub fractal: Fractal,
+    pub center: (DoubleDouble, DoubleDouble),
+    // height of the view in the complex plane in the first frame
+    pub height: f64,
+    pub factor: f64,
+    // the rows of a frame are split into this many bands
+    pub threads: usize,
+}
+
+// plain f64 iteration drifts near the boundary long before neighboring pixels
+// get the same coordinates, perturbation stays accurate below this pixel size
+const DIRECT_LIMIT: f64 = 1e-6;
+
+// and double-double centers until here
+const DEPTH_LIMIT: f64 = 1e-29;
+
+impl Zoom {
+    pub fn new(fractal: Fractal, center: (DoubleDouble, DoubleDouble), factor: f64) -> Self {
+        let height = (fractal.view.imax - fractal.view.imin).abs();
+        Self {
+            fractal: Fractal { formula: Arc::new(Mandelbrot), ..fractal },
+            center,
+            height,
+            factor,
+            threads: 1,
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    fn magnitude(&self) -> f64 {
+        self.center.0.hi.abs().max(self.center.1.hi.abs()).max(1.0)
+    }
+
+    // size of a pixel in frame number frame of an image h pixels high
+    pub fn spacing(&self, frame: usize, h: usize) -> f64 {
+        self.height * self.factor.powi(frame as i32) / h as f64
+    }
+
+    // number of frames until even double-double runs out of precision
+    pub fn frames(&self, h: usize) -> usize {
+        let depth = (DEPTH_LIMIT * self.magnitude() * h as f64 / self.height).ln() / self.factor.ln();
+        depth.max(1.0) as usize
+    }
+
+    // row-major colors of a w x h frame
+    pub fn frame(&self, frame: usize, w: usize, h: usize) -> Vec<(u8, u8, u8)> {
+        let spacing = self.spacing(frame, h);
+        let times = if spacing > DIRECT_LIMIT * self.magnitude() {
+            self.direct(spacing, w, h)
+        } else {
+            self.perturbed(spacing, w, h)
+        };
+        self.fractal.colorize(&times[..])
+    }
+
+    // row-major escape times of h rows, every thread computes the rows
+    // start..end of a band with band(start, end)
+    fn bands<F: Fn(usize, usize) -> Vec<Option<f64>> + Sync>(&self, h: usize, band: F) -> Vec<Option<f64>> {
+        let rows = h.div_ceil(self.threads.max(1)).max(1);
+        std::thread::scope(|scope| {
+            let band = &band;
+            let handles: Vec<_> = (0..h)
+                .step_by(rows)
+                .map(|start| scope.spawn(move || band(start, (start + rows).min(h))))
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().expect("zoom thread panicked")).collect()
+        })
+    }
+
+    fn direct(&self, spacing: f64, w: usize, h: usize) -> Vec<Option<f64>> {
+        let (r, i) = (self.center.0.to_f64(), self.center.1.to_f64());
+        let (half_w, top) = (w as f64 / 2.0 * spacing, i + h as f64 / 2.0 * spacing);
+        self.bands(h, |start, end| {
+            let fractal = Fractal {
+                view: View { rmin: r - half_w, rmax: r + half_w, imin: top - end as f64 * spacing, imax: top - start as f64 * spacing },
+                ..self.fractal.clone()
+            };
+            fractal.escape_times(w, end - start)
+        })
+    }
+
+    // the orbit of the center computed with double-double, rounded to f64
+    fn reference_orbit(&self) -> Vec<Complex> {
+        let radius_sqr = self.fractal.escape_radius * self.fractal.escape_radius;
+        let (cr, ci) = self.center;
+        let (mut zr, mut zi) = (DoubleDouble::ZERO, DoubleDouble::ZERO);
+        let mut orbit = vec![Complex::from(0.0, 0.0)];
+        for _ in 0..self.fractal.iterations {
+            let r = zr * zr - zi * zi + cr;
+            zi = (zr + zr) * zi + ci;
+            zr = r;
+            let z = Complex::from(zr.to_f64(), zi.to_f64());
+            orbit.push(z);
+            if z.norm_sqr() > radius_sqr {
+                break;
+            }
+        }
+        orbit
+    }
+
+    // only the small difference of every pixel to the reference orbit is iterated,
+    // in f64: d' = 2 Z d + d^2 + dc
+    fn perturbed(&self, spacing: f64, w: usize, h: usize) -> Vec<Option<f64>> {
+        let orbit = self.reference_orbit();
+        self.bands(h, |start, end| self.perturbed_rows(&orbit[..], spacing, w, h, start..end))
+    }
+
+    fn perturbed_rows(&self, orbit: &[Complex], spacing: f64, w: usize, h: usize, rows: std::ops::Range<usize>) -> Vec<Option<f64>> {
+        let radius_sqr = self.fractal.escape_radius * self.fractal.escape_radius;
+        let mut times = Vec::with_capacity(w * rows.len());
+        for y in rows {
+            for x in 0..w {
+                let dc = Complex::from((x as f64 - w as f64 / 2.0) * spacing, (h as f64 / 2.0 - (y + 1) as f64) * spacing);
+                let mut dz = Complex::from(0.0, 0.0);
+                let mut m = 0;
+                let mut time = None;
+                for i in 1..=self.fractal.iterations {
+                    dz = (orbit[m] * dz).scale(2.0) + dz * dz + dc;
+                    m += 1;
+                    let z = orbit[m] + dz;
+                    let norm_sqr = z.norm_sqr();
+                    if norm_sqr > radius_sqr {
+                        time = Some(self.fractal.escape_time(i, norm_sqr));
+                        break;
+                    }
+                    // continue from the start of the orbit when z gets closer to zero
+                    // than to the reference, or the reference escaped
+                    if norm_sqr < dz.norm_sqr() || m == orbit.len() - 1 {
+                        dz = z;
+                        m = 0;
+                    }
+                }
+                times.push(time);
+            }
+        }
+        times
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dd(s: &str) -> DoubleDouble {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn arithmetic_keeps_the_low_part() {
+        // 1 + 2^-60 doesn't fit in one f64
+        let tiny = DoubleDouble::from(2f64.powi(-60));
+        let one = DoubleDouble::from(1.0);
+        let sum = one + tiny;
+        assert_eq!((sum.hi, sum.lo), (1.0, 2f64.powi(-60)));
+        assert_eq!(sum - one, tiny);
+
+        // (1 + e)^2 = 1 + 2e + e^2
+        let e = 2f64.powi(-30);
+        let square = DoubleDouble::from(1.0 + e) * DoubleDouble::from(1.0 + e);
+        assert_eq!(square.hi, 1.0 + 2.0 * e);
+        assert_eq!(square.lo, e * e);
+
+        let third = DoubleDouble::from(1.0) / DoubleDouble::from(3.0);
+        let error = third * DoubleDouble::from(3.0) - DoubleDouble::from(1.0);
+        assert!(error.to_f64().abs() < 1e-30, "{:?}", error);
+        assert_eq!(third.hi, 1.0 / 3.0);
+        assert!(third.lo != 0.0);
+    }
+
+    #[test]
+    fn parses_digits_beyond_f64() {
+        assert_eq!(dd("1.5"), DoubleDouble::from(1.5));
+        assert_eq!(dd("-2"), DoubleDouble::from(-2.0));
+        assert_eq!(dd("+.25"), DoubleDouble::from(0.25));
+        assert_eq!(dd("3."), DoubleDouble::from(3.0));
+
+        // the digits after the 17th only show up in the low part
+        let a = dd("0.1000000000000000000000000001");
+        let b = dd("0.1");
+        assert_eq!(a.hi, b.hi);
+        let difference = (a - b).to_f64();
+        assert!((difference - 1e-28).abs() < 1e-32, "{}", difference);
+
+        let x = dd("-0.743643887037158704752191506114774");
+        assert_eq!(x.hi, "-0.743643887037158704752191506114774".parse::<f64>().unwrap());
+        assert!(x.lo != 0.0);
+        assert_eq!(-x, dd("0.743643887037158704752191506114774"));
+
+        for s in ["", "-", ".", "1.2.3", "1e5", "0x10", "abc", "1 2"] {
+            assert!(s.parse::<DoubleDouble>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn perturbation_matches_direct_iteration_at_shallow_zoom() {
+        let fractal = Fractal { iterations: 200, coloring: crate::mandel::Coloring::Plain, ..Fractal::default() };
+        let zoom = Zoom::new(fractal, (dd("-0.75"), dd("0.1")), 0.9);
+        let (w, h) = (40, 30);
+        let spacing = 0.2 / h as f64;
+        let direct = zoom.direct(spacing, w, h);
+        let perturbed = zoom.perturbed(spacing, w, h);
+        // points near the boundary may escape one iteration apart through rounding
+        let same = direct.iter().zip(&perturbed).filter(|(a, b)| a == b).count();
+        assert!(same * 100 >= w * h * 98, "{} of {} agree", same, w * h);
+        assert!(direct.iter().any(|t| t.is_none()) && direct.iter().any(|t| t.is_some()));
+    }
+
+    #[test]
+    fn threads_split_the_rows_without_changing_the_frame() {
+        let fractal = Fractal { iterations: 300, ..Fractal::default() };
+        let zoom = Zoom::new(fractal, (dd("-0.743643887037158704752191506114774"), dd("0.131825904205311970493132056385139")), 0.9);
+        let (w, h) = (23, 17);
+        let direct = zoom.direct(1e-3, w, h);
+        let perturbed = zoom.perturbed(1e-12, w, h);
+        for threads in [2, 5, 17, 40] {
+            let split = zoom.clone().with_threads(threads);
+            assert_eq!(split.perturbed(1e-12, w, h), perturbed);
+            // every band gets a view of its own, which can round the coordinates differently
+            let times = split.direct(1e-3, w, h);
+            assert_eq!(times.len(), w * h);
+            let same = direct.iter().zip(&times).filter(|(a, b)| a == b).count();
+            assert!(same * 100 >= w * h * 98, "{} threads: {} of {} agree", threads, same, w * h);
+        }
+    }
+}
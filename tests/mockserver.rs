@@ -0,0 +1,299 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use mockserver::{Framebuffer, MockServer, Mode};
+use pixelflut::automaton::{ColorMap, Rule, Runner, Topology};
+use pixelflut::canvas::Canvas;
+use pixelflut::client::PixelflutClient;
+use pixelflut::pool::{ConnectionPool, Sharding};
+use pixelflut::primitive::{Pixel, Rect};
+use pixelflut::protocol::{BinaryProtocol, Protocol, TextProtocol};
+use pixelflut::session::{Backoff, Session};
+
+fn connect<P: Protocol>(server: &MockServer, protocol: P) -> PixelflutClient<TcpStream, P> {
+    PixelflutClient::with_protocol(TcpStream::connect(server.addr()).unwrap(), protocol).unwrap()
+}
+
+fn session<P: Protocol + Clone>(server: &MockServer, protocol: P) -> Session<TcpStream, P> {
+    let addr = server.addr();
+    Session::new(move || TcpStream::connect(addr), protocol, Backoff::default()).unwrap()
+}
+
+fn pattern(rect: Rect) -> Vec<(u8, u8, u8)> {
+    rect.ys_abs()
+        .flat_map(|y| rect.xs_abs().map(move |x| (x as u8, y as u8, (x * 7 + y * 13) as u8)))
+        .collect()
+}
+
+fn region(fb: &Framebuffer, rect: Rect) -> Vec<(u8, u8, u8)> {
+    rect.ys_abs()
+        .flat_map(|y| rect.xs_abs().map(move |x| (x, y)))
+        .map(|(x, y)| fb.get(x, y).unwrap())
+        .collect()
+}
+
+// the server handles every connection on its own thread, a get on the same
+// connection returns only after everything sent before it was applied
+fn sync<S: Read + Write, P: Protocol>(client: &mut PixelflutClient<S, P>) {
+    client.get(&mut Pixel { x: 0, y: 0, color: (0, 0, 0) }).unwrap();
+}
+
+// for writes over other connections there is nothing to wait on
+fn eventually(server: &MockServer, f: impl Fn(&Framebuffer) -> bool) -> bool {
+    for _ in 0..200 {
+        if f(&server.snapshot()) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+fn draw_and_read_back<P: Protocol>(mode: Mode, protocol: P) {
+    let server = MockServer::start(mode, 64, 48).unwrap();
+    let mut client = connect(&server, protocol);
+    assert_eq!((client.info().width, client.info().height), (64, 48));
+
+    client.print(&Pixel { x: 3, y: 4, color: (10, 20, 30) }).unwrap();
+    client.print(&Pixel { x: 63, y: 47, color: (255, 0, 128) }).unwrap();
+    let rect = Rect { x: 10, y: 5, w: 20, h: 15 };
+    let colors = pattern(rect);
+    client.rectangle_print(&colors[..], rect).unwrap();
+    let fill = Rect { x: 40, y: 30, w: 6, h: 4 };
+    client.rectangle_fill((1, 2, 3), fill).unwrap();
+    sync(&mut client);
+
+    let fb = server.snapshot();
+    assert_eq!(fb.get(3, 4), Some((10, 20, 30)));
+    assert_eq!(fb.get(63, 47), Some((255, 0, 128)));
+    assert_eq!(region(&fb, rect), colors);
+    assert_eq!(region(&fb, fill), vec![(1, 2, 3); 24]);
+    assert_eq!(fb.get(46, 30), Some((0, 0, 0)));
+    assert_eq!(fb.get(40, 34), Some((0, 0, 0)));
+
+    let mut px = Pixel { x: 3, y: 4, color: (0, 0, 0) };
+    client.get(&mut px).unwrap();
+    assert_eq!(px.color, (10, 20, 30));
+    let mut read = vec![(0, 0, 0); rect.w * rect.h];
+    client.rectangle_get(&mut read[..], rect).unwrap();
+    assert_eq!(read, colors);
+}
+
+#[test]
+fn binary_client_draws_and_reads_back() {
+    draw_and_read_back(Mode::Binary, BinaryProtocol);
+}
+
+#[test]
+fn text_client_draws_and_reads_back() {
+    draw_and_read_back(Mode::Text, TextProtocol);
+}
+
+#[test]
+fn binary_info_reports_buffer_sizes() {
+    let server = MockServer::start(Mode::Binary, 32, 16).unwrap();
+    server.set_buffer_sizes(100, 200);
+    let info = connect(&server, BinaryProtocol).info();
+    assert_eq!((info.width, info.height, info.recv_buffer_size, info.send_buffer_size), (32, 16, 100, 200));
+}
+
+#[test]
+fn binary_rectangles_are_tiled_to_small_buffers() {
+    let server = MockServer::start(Mode::Binary, 64, 48).unwrap();
+    // room for 14 pixels after the 8 byte header
+    server.set_buffer_sizes(64, 64);
+    let mut client = connect(&server, BinaryProtocol);
+    let rect = Rect { x: 1, y: 2, w: 30, h: 7 };
+    let colors = pattern(rect);
+    client.rectangle_print(&colors[..], rect).unwrap();
+    sync(&mut client);
+    assert_eq!(region(&server.snapshot(), rect), colors);
+
+    let mut read = vec![(0, 0, 0); rect.w * rect.h];
+    client.rectangle_get(&mut read[..], rect).unwrap();
+    assert_eq!(read, colors);
+}
+
+#[test]
+fn text_offset_moves_later_pixels() {
+    let server = MockServer::start(Mode::Text, 16, 16).unwrap();
+    let mut client = connect(&server, TextProtocol);
+    client.offset(5, 6).unwrap();
+    client.print(&Pixel { x: 1, y: 2, color: (9, 8, 7) }).unwrap();
+    let mut px = Pixel { x: 1, y: 2, color: (0, 0, 0) };
+    client.get(&mut px).unwrap();
+    assert_eq!(px.color, (9, 8, 7));
+    assert_eq!(server.pixel(6, 8), Some((9, 8, 7)));
+    assert_eq!(server.pixel(1, 2), Some((0, 0, 0)));
+}
+
+// hand-encoded commands, so the server is not only tested against our own encoder
+fn rect_command(command: u8, x: usize, y: usize, w: usize, h: usize) -> [u8; 8] {
+    [
+        command,
+        x as u8,
+        (x >> 8) as u8,
+        y as u8,
+        (y >> 8) as u8,
+        w as u8,
+        h as u8,
+        ((w >> 8) & 0x0f) as u8 | ((h >> 4) & 0xf0) as u8,
+    ]
+}
+
+fn raw_rect_roundtrip(width: usize, height: usize, rect: Rect) {
+    let server = MockServer::start(Mode::Binary, width, height).unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    let colors = pattern(rect);
+    stream.write_all(&rect_command(b'p', rect.x, rect.y, rect.w, rect.h)).unwrap();
+    let data: Vec<u8> = colors.iter().flat_map(|c| [c.0, c.1, c.2, 0]).collect();
+    stream.write_all(&data[..]).unwrap();
+
+    stream.write_all(&rect_command(b'g', rect.x, rect.y, rect.w, rect.h)).unwrap();
+    let mut reply = vec![0u8; rect.w * rect.h * 4];
+    stream.read_exact(&mut reply[..]).unwrap();
+    assert_eq!(reply, data);
+    assert_eq!(region(&server.snapshot(), rect), colors);
+}
+
+#[test]
+fn mockserver_decodes_wide_rects() {
+    // 300 needs the low nibble of the last byte
+    raw_rect_roundtrip(320, 4, Rect { x: 3, y: 1, w: 300, h: 2 });
+}
+
+#[test]
+fn mockserver_decodes_tall_rects() {
+    // 260 needs the high nibble of the last byte
+    raw_rect_roundtrip(4, 270, Rect { x: 1, y: 5, w: 2, h: 260 });
+}
+
+#[test]
+fn mockserver_get_outside_the_screen_is_black() {
+    let server = MockServer::start(Mode::Binary, 4, 4).unwrap();
+    server.with_framebuffer(|fb| fb.fill((50, 60, 70)));
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(&rect_command(b'g', 2, 2, 4, 1)).unwrap();
+    let mut reply = [0u8; 16];
+    stream.read_exact(&mut reply[..]).unwrap();
+    assert_eq!(reply, [50, 60, 70, 0, 50, 60, 70, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn pool_draws_every_shard() {
+    for sharding in [Sharding::RoundRobin, Sharding::Region] {
+        let server = MockServer::start(Mode::Binary, 40, 30).unwrap();
+        let mut session = session(&server, BinaryProtocol);
+        let pixels: Vec<Pixel> = (0..30)
+            .flat_map(|y| (0..40).map(move |x| Pixel { x, y, color: (x as u8, y as u8, 1) }))
+            .collect();
+        let mut pool = ConnectionPool::connect(&session, 2, 64).unwrap();
+        let stats = pool.draw(&mut session, &pixels[..], sharding).unwrap();
+        assert_eq!(stats.pixels, 1200);
+        assert!(eventually(&server, |fb| pixels.iter().all(|px| fb.get(px.x, px.y) == Some(px.color))));
+    }
+}
+
+#[test]
+fn canvas_upload_sends_the_difference() {
+    let server = MockServer::start(Mode::Binary, 32, 32).unwrap();
+    server.with_framebuffer(|fb| fb.fill((5, 5, 5)));
+    let mut client = connect(&server, BinaryProtocol);
+    let rect = Rect { x: 4, y: 4, w: 16, h: 8 };
+    let mut canvas = Canvas::new(rect);
+    canvas.fetch(&mut client).unwrap();
+    assert_eq!(canvas.get(0, 0), (5, 5, 5));
+    canvas.set(0, 0, (200, 0, 0));
+    canvas.set(15, 7, (0, 200, 0));
+    assert_eq!(canvas.diff().len(), 2);
+    canvas.upload(&mut client, 1024).unwrap();
+    sync(&mut client);
+
+    let fb = server.snapshot();
+    assert_eq!(fb.get(4, 4), Some((200, 0, 0)));
+    assert_eq!(fb.get(19, 11), Some((0, 200, 0)));
+    assert_eq!(fb.get(5, 4), Some((5, 5, 5)));
+    assert!(canvas.diff().is_empty());
+}
+
+const MARK: (u8, u8, u8) = (10, 200, 30);
+
+fn life(rect: Rect) -> Runner {
+    Runner::new(rect, Rule::life(), Topology::Bounded, ColorMap::default())
+}
+
+// white cells of the region as coordinates relative to it
+fn alive(fb: &Framebuffer, rect: Rect) -> Vec<(usize, usize)> {
+    rect.ys_abs()
+        .flat_map(|y| rect.xs_abs().map(move |x| (x, y)))
+        .filter(|&(x, y)| fb.get(x, y) == Some((255, 255, 255)))
+        .map(|(x, y)| (x - rect.x, y - rect.y))
+        .collect()
+}
+
+#[test]
+fn runner_only_draws_inside_its_region() {
+    let server = MockServer::start(Mode::Binary, 20, 16).unwrap();
+    server.with_framebuffer(|fb| fb.fill(MARK));
+    let mut session = session(&server, BinaryProtocol);
+    let rect = Rect { x: 4, y: 3, w: 7, h: 5 };
+    let mut runner = life(rect);
+    for x in 2..5 {
+        runner.automaton.set(x, 2, 1);
+    }
+    runner.run(&mut session, Some(1), 1024).unwrap();
+    sync(session.client().unwrap());
+
+    let fb = server.snapshot();
+    assert_eq!(alive(&fb, rect), [(3, 1), (3, 2), (3, 3)]);
+    for y in 0..16 {
+        for x in 0..20 {
+            let inside = (rect.x..rect.x + rect.w).contains(&x) && (rect.y..rect.y + rect.h).contains(&y);
+            if !inside {
+                assert_eq!(fb.get(x, y), Some(MARK), "{}, {}", x, y);
+            } else if !alive(&fb, rect).contains(&(x - rect.x, y - rect.y)) {
+                assert_eq!(fb.get(x, y), Some((0, 0, 0)), "{}, {}", x, y);
+            }
+        }
+    }
+}
+
+#[test]
+fn reacting_runner_picks_up_pixels_drawn_by_others() {
+    let server = MockServer::start(Mode::Binary, 12, 12).unwrap();
+    let mut session = session(&server, BinaryProtocol);
+    let rect = Rect { x: 2, y: 2, w: 8, h: 8 };
+    let mut runner = life(rect).with_react(true);
+    runner.fetch(&mut session).unwrap();
+
+    // someone else draws a blinker into the region
+    server.with_framebuffer(|fb| {
+        for x in 4..7 {
+            fb.set(x, 6, (255, 255, 255));
+        }
+    });
+    runner.step(&mut session, 1024).unwrap();
+    sync(session.client().unwrap());
+    assert_eq!(alive(&server.snapshot(), rect), [(3, 3), (3, 4), (3, 5)]);
+
+    // without reacting, later pixels of others don't become cells: the lone one
+    // would die, but it is neither read nor cleared
+    let mut runner = life(rect);
+    runner.fetch(&mut session).unwrap();
+    server.with_framebuffer(|fb| fb.set(2, 2, (255, 255, 255)));
+    runner.step(&mut session, 1024).unwrap();
+    sync(session.client().unwrap());
+    assert_eq!(alive(&server.snapshot(), rect), [(0, 0), (2, 4), (3, 4), (4, 4)]);
+}
+
+#[test]
+fn runner_keeps_its_generation_rate() {
+    let server = MockServer::start(Mode::Binary, 8, 8).unwrap();
+    let mut session = session(&server, BinaryProtocol);
+    let mut runner = life(Rect { x: 0, y: 0, w: 8, h: 8 }).with_rate(Some(20.0));
+    let start = Instant::now();
+    runner.run(&mut session, Some(4), 1024).unwrap();
+    // every generation waits for its 50ms slot
+    assert!(start.elapsed() >= Duration::from_millis(190), "{:?}", start.elapsed());
+}